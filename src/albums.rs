@@ -0,0 +1,174 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::Song;
+
+/// a `TrackTotal` this far past the highest track that is there is taken for a broken tag, like
+/// the 999 some taggers write
+const MAX_TRACKS_AFTER_LAST: i32 = 30;
+
+/// an album as far as the library knows it, with what is missing from it
+#[derive(Debug, Clone)]
+pub struct Album {
+    pub artist: String,
+    pub name: String,
+    /// number of tracks of this album in the library
+    pub tracks: usize,
+    /// `(disc, track)` of every track that should be there but isn't
+    pub missing_tracks: Vec<(i32, i32)>,
+    /// discs that should be there but have no tracks at all
+    pub missing_discs: Vec<i32>,
+}
+
+impl Album {
+    pub fn is_complete(&self) -> bool {
+        self.missing_tracks.is_empty() && self.missing_discs.is_empty()
+    }
+
+    /// human readable list of the gaps, like `disc 1: 3, 7; disc 2 missing`
+    pub fn describe_gaps(&self) -> String {
+        let mut by_disc: BTreeMap<i32, Vec<String>> = BTreeMap::new();
+        for (disc, track) in &self.missing_tracks {
+            by_disc.entry(*disc).or_default().push(track.to_string());
+        }
+        by_disc
+            .into_iter()
+            .map(|(disc, tracks)| format!("disc {}: {}", disc, tracks.join(", ")))
+            .chain(
+                self.missing_discs
+                    .iter()
+                    .map(|d| format!("disc {} missing", d)),
+            )
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+/// the songs of every album, keyed by `(artist, album)`. songs without an album tag are
+/// left out.
+pub fn group(songs: &[Song]) -> BTreeMap<(String, String), Vec<&Song>> {
+    let mut grouped: BTreeMap<(String, String), Vec<&Song>> = BTreeMap::new();
+    for song in songs {
+        let Some(name) = song.album_name.clone() else {
+            continue;
+        };
+        let artist = song
+            .album_artist
+            .clone()
+            .or(song.track_artist.clone())
+            .unwrap_or_default();
+        grouped.entry((artist, name)).or_default().push(song);
+    }
+    grouped
+}
+
+/// group `songs` into albums and check each one for gaps. songs without an album tag are
+/// left out.
+///
+/// the track numbers on each disc are compared with the highest `TrackTotal` on that disc,
+/// and the discs with the highest `DiscTotal`. without a total, or with one that can't be
+/// right, only the holes below the highest track number that is there can be found.
+pub fn albums(songs: &[Song]) -> Vec<Album> {
+    group(songs)
+        .into_iter()
+        .map(|((artist, name), songs)| {
+            let mut discs: BTreeMap<i32, (BTreeSet<i32>, Option<i32>)> = BTreeMap::new();
+            for song in &songs {
+                let (present, total) = discs.entry(song.disc_number.unwrap_or(1)).or_default();
+                if let Some(track) = song.track_number {
+                    present.insert(track);
+                }
+                *total = (*total).max(song.track_total);
+            }
+            let missing_tracks = discs
+                .iter()
+                .flat_map(|(disc, (present, total))| {
+                    let highest = present.last().copied().unwrap_or(0);
+                    let total = total.filter(|t| *t <= highest + MAX_TRACKS_AFTER_LAST);
+                    let last = total.unwrap_or(highest);
+                    (1..=last)
+                        .filter(|t| !present.contains(t))
+                        .map(move |t| (*disc, t))
+                })
+                .collect();
+            let disc_total = songs.iter().filter_map(|s| s.disc_total).max().unwrap_or(0);
+            let missing_discs = (1..=disc_total)
+                .filter(|d| !discs.contains_key(d))
+                .collect();
+            Album {
+                artist,
+                name,
+                tracks: songs.len(),
+                missing_tracks,
+                missing_discs,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// a track of "album" by "artist"
+    fn song(disc: Option<i32>, track: i32, total: Option<i32>) -> Song {
+        let mut song = Song::new(PathBuf::from(format!("{:?} {}", disc, track)));
+        song.album_artist = Some("artist".to_string());
+        song.album_name = Some("album".to_string());
+        song.disc_number = disc;
+        song.track_number = Some(track);
+        song.track_total = total;
+        song
+    }
+
+    fn album(songs: &[Song]) -> Album {
+        let mut albums = albums(songs);
+        assert_eq!(albums.len(), 1);
+        albums.remove(0)
+    }
+
+    #[test]
+    fn finds_the_gaps_on_a_disc() {
+        let songs = [
+            song(Some(1), 1, Some(4)),
+            song(Some(1), 2, Some(4)),
+            song(Some(1), 4, Some(4)),
+            song(Some(2), 1, Some(2)),
+            song(Some(2), 2, Some(2)),
+        ];
+        let album = album(&songs);
+        assert_eq!(album.missing_tracks, [(1, 3)]);
+        assert!(album.missing_discs.is_empty());
+    }
+
+    #[test]
+    fn finds_missing_tracks_at_the_end_and_whole_discs() {
+        let mut songs = vec![song(Some(1), 1, Some(3)), song(Some(3), 1, Some(1))];
+        songs.iter_mut().for_each(|s| s.disc_total = Some(3));
+        let album = album(&songs);
+        assert_eq!(album.missing_tracks, [(1, 2), (1, 3)]);
+        assert_eq!(album.missing_discs, [2]);
+        assert_eq!(album.describe_gaps(), "disc 1: 2, 3; disc 2 missing");
+    }
+
+    #[test]
+    fn without_a_total_only_holes_count() {
+        let songs = [song(Some(1), 1, None), song(Some(1), 3, None)];
+        assert_eq!(album(&songs).missing_tracks, [(1, 2)]);
+        let songs = [song(Some(1), 1, None), song(Some(1), 2, None)];
+        assert!(album(&songs).is_complete());
+    }
+
+    #[test]
+    fn no_disc_number_is_disc_one() {
+        let songs = [song(None, 1, Some(3)), song(Some(1), 3, Some(3))];
+        assert_eq!(album(&songs).missing_tracks, [(1, 2)]);
+    }
+
+    #[test]
+    fn ignores_totals_that_cant_be_right() {
+        let songs = [song(Some(1), 1, Some(999)), song(Some(1), 2, Some(999))];
+        assert!(album(&songs).is_complete());
+    }
+}
@@ -0,0 +1,38 @@
+use std::path::PathBuf;
+
+use iced::{
+    futures::{SinkExt, Stream},
+    stream,
+};
+
+/// run `job` on every group of files, one group at a time on the blocking pool, and yield
+/// its results per file once the group is done. `job` gets the paths of the group with what
+/// goes along with them, and returns a result for each path in the same order.
+pub fn run_all<G, T, F>(
+    groups: Vec<(Vec<PathBuf>, G)>,
+    job: F,
+) -> impl Stream<Item = (PathBuf, Result<T, String>)>
+where
+    G: Send + 'static,
+    T: Send + 'static,
+    F: Fn(&[PathBuf], G) -> Vec<Result<T, String>> + Clone + Send + 'static,
+{
+    stream::channel(16, move |mut output| async move {
+        for (paths, extra) in groups {
+            let job_paths = paths.clone();
+            let job = job.clone();
+            let results = match tokio::task::spawn_blocking(move || job(&job_paths, extra)).await {
+                Ok(results) => results,
+                Err(e) => {
+                    let error = format!("analysis panicked: {}", e);
+                    paths.iter().map(|_| Err(error.clone())).collect()
+                }
+            };
+            for (path, result) in paths.into_iter().zip(results) {
+                if output.send((path, result)).await.is_err() {
+                    return;
+                }
+            }
+        }
+    })
+}
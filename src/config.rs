@@ -0,0 +1,185 @@
+use std::{fs, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dsp::EffectSettings,
+    eq::{self, EqPreset},
+    output::OutputKind,
+    playback::FadeCurve,
+    queue::{RepeatMode, ShuffleMode},
+    replaygain::ReplayGainMode,
+};
+
+/// what happens to a file when it's accepted out of the inbox
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    Move,
+    Copy,
+}
+
+/// user settings, read from `config.toml` in the thump config directory.
+/// every field has a default so a partial (or missing) file is fine.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// root of the curated library
+    pub library_dir: PathBuf,
+    /// folder new downloads land in. files in here show up in the inbox instead of the library
+    pub downloads_dir: Option<PathBuf>,
+    /// where files go inside `library_dir`, see `template::render` for the syntax
+    pub library_template: String,
+    pub import_mode: ImportMode,
+    /// only files with one of these extensions are looked at when scanning
+    pub audio_extensions: Vec<String>,
+    /// gitignore style patterns skipped when scanning, on top of any `.thumpignore` files
+    pub exclude: Vec<String>,
+    /// how many directories deep a scan goes below its root, no limit if unset
+    pub max_scan_depth: Option<usize>,
+    /// keep scans on the file system their root is on, so mounted network drives are skipped
+    pub one_file_system: bool,
+    /// enter symlinked directories while scanning. loops are detected either way
+    pub follow_symlinks: bool,
+    /// seconds consecutive tracks overlap, 0 to 12. 0 plays them gaplessly
+    pub crossfade_secs: f32,
+    pub crossfade_curve: FadeCurve,
+    /// crossfade between tracks of the same album too, instead of keeping albums gapless
+    pub crossfade_same_album: bool,
+    pub replay_gain: ReplayGainMode,
+    /// dB added on top of the ReplayGain tags
+    pub replay_gain_preamp: f32,
+    /// turn the gain down where the peak tags say it would clip
+    pub replay_gain_prevent_clipping: bool,
+    /// write the results of the loudness analysis to the files as ReplayGain tags
+    pub write_replay_gain_tags: bool,
+    /// name of the equalizer preset in use, none for no equalizer
+    pub eq_preset: Option<String>,
+    /// keep the preset selected but don't apply it
+    pub eq_bypass: bool,
+    /// presets made or imported by the user, on top of the built in ones
+    pub eq_presets: Vec<EqPreset>,
+    /// effects run after the equalizer, in order
+    pub dsp_chain: Vec<EffectSettings>,
+    /// position of the volume slider from 0 to 1, on a dB scale
+    pub volume: f32,
+    pub muted: bool,
+    /// playback speed, 0.5 to 3, the pitch stays the same
+    pub speed: f32,
+    /// semitones the pitch is moved, -12 to 12, the tempo stays the same
+    pub pitch_semitones: f32,
+    pub shuffle: ShuffleMode,
+    /// seed the shuffle order is made from, so it can be played back. a new one every time
+    /// shuffle is turned on if unset
+    pub shuffle_seed: Option<u64>,
+    pub repeat: RepeatMode,
+    /// name of the output device to play on, the default one if unset or missing
+    pub output_device: Option<String>,
+    /// play on a sound card, or on one of the backends for running without one
+    pub output: OutputKind,
+    /// serve what's playing over http on this port, to listen in from other machines.
+    /// off if unset
+    pub stream_port: Option<u16>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            library_dir: dirs::audio_dir()
+                .or_else(|| dirs::home_dir().map(|home| home.join("Music")))
+                .unwrap_or_else(|| PathBuf::from(".")),
+            downloads_dir: None,
+            library_template: "{album_artist}/{year} - {album}/{disc}-{track:02} {title}.{ext}"
+                .to_string(),
+            import_mode: ImportMode::Move,
+            audio_extensions: [
+                "mp3", "flac", "ogg", "oga", "opus", "m4a", "mp4", "aac", "wav", "aiff", "aif",
+                "wv", "ape", "mpc",
+            ]
+            .map(String::from)
+            .to_vec(),
+            exclude: ["*.part", "*.!qB", "*.crdownload", "*.tmp"]
+                .map(String::from)
+                .to_vec(),
+            max_scan_depth: Some(16),
+            one_file_system: false,
+            follow_symlinks: true,
+            crossfade_secs: 0.0,
+            crossfade_curve: FadeCurve::EqualPower,
+            crossfade_same_album: false,
+            replay_gain: ReplayGainMode::Off,
+            replay_gain_preamp: 0.0,
+            replay_gain_prevent_clipping: true,
+            write_replay_gain_tags: false,
+            eq_preset: None,
+            eq_bypass: false,
+            eq_presets: Vec::new(),
+            dsp_chain: Vec::new(),
+            volume: 1.0,
+            muted: false,
+            speed: 1.0,
+            pitch_semitones: 0.0,
+            shuffle: ShuffleMode::Off,
+            shuffle_seed: None,
+            repeat: RepeatMode::Off,
+            output_device: None,
+            output: OutputKind::Device,
+            stream_port: None,
+        }
+    }
+}
+
+impl Config {
+    /// the directory thump keeps its config and data files in
+    pub fn dir() -> PathBuf {
+        dirs::config_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("thump")
+    }
+
+    fn path() -> PathBuf {
+        Config::dir().join("config.toml")
+    }
+
+    /// write the config file, for settings changed from inside the app
+    pub fn save(&self) {
+        let path = Config::path();
+        let contents = toml::to_string_pretty(self).expect("failed to serialize config");
+        if let Err(e) = fs::create_dir_all(Config::dir()).and_then(|_| fs::write(&path, contents)) {
+            println!("error: failed to save config to {:?}: {}", path, e);
+        }
+    }
+
+    /// every equalizer preset, built in ones first. a user preset with the name of a built
+    /// in one replaces it.
+    pub fn all_eq_presets(&self) -> Vec<EqPreset> {
+        let mut presets: Vec<EqPreset> = eq::builtin_presets()
+            .into_iter()
+            .filter(|p| !self.eq_presets.iter().any(|u| u.name == p.name))
+            .collect();
+        presets.extend(self.eq_presets.iter().cloned());
+        presets
+    }
+
+    /// the preset the equalizer should run with, `None` when it's off or bypassed
+    pub fn equalizer(&self) -> Option<EqPreset> {
+        if self.eq_bypass {
+            return None;
+        }
+        let name = self.eq_preset.as_ref()?;
+        self.all_eq_presets().into_iter().find(|p| &p.name == name)
+    }
+
+    /// load the config file, falling back to the defaults if it's missing or broken
+    pub fn load() -> Config {
+        let path = Config::path();
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(_) => return Config::default(),
+        };
+        toml::from_str(&contents).unwrap_or_else(|e| {
+            println!("error: failed to parse {:?}, using defaults: {}", path, e);
+            Config::default()
+        })
+    }
+}
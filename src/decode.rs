@@ -0,0 +1,349 @@
+use std::{fs::File, io::ErrorKind, path::Path, time::Duration};
+
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{Decoder, DecoderOptions},
+    errors::Error,
+    formats::{FormatOptions, FormatReader, SeekMode, SeekTo},
+    io::MediaSourceStream,
+    meta::{MetadataOptions, MetadataRevision},
+    probe::{Hint, ProbeResult},
+    units::TimeBase,
+};
+
+/// find out what kind of file `path` is and get a reader for its container
+fn probe(path: &Path) -> Result<ProbeResult, Error> {
+    let file = File::open(path)?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+    let format_options = FormatOptions {
+        enable_gapless: true,
+        ..Default::default()
+    };
+    symphonia::default::get_probe().format(&hint, mss, &format_options, &MetadataOptions::default())
+}
+
+/// length of the audio in `path`, by adding up the packets of its default track without
+/// decoding them. for when the container doesn't say, it reads the whole file.
+pub fn count_packets<T: AsRef<Path>>(path: T) -> Result<Duration, Error> {
+    let mut format = probe(path.as_ref())?.format;
+    let track = format
+        .default_track()
+        .ok_or(Error::Unsupported("no default track"))?;
+    let (track_id, params) = (track.id, track.codec_params.clone());
+    let mut frames: u64 = 0;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(Error::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        };
+        if packet.track_id() == track_id {
+            let trim = (packet.trim_start + packet.trim_end) as u64;
+            frames += packet.dur.saturating_sub(trim);
+        }
+    }
+    match (params.time_base, params.sample_rate) {
+        (Some(time_base), _) => {
+            let time = time_base.calc_time(frames);
+            Ok(Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac))
+        }
+        (None, Some(rate)) => Ok(Duration::from_secs_f64(frames as f64 / rate as f64)),
+        (None, None) => Err(Error::Unsupported("unknown sample rate")),
+    }
+}
+
+/// decodes a file with symphonia and hands out interleaved `f32` blocks.
+/// encoder delay and padding are cut off, so consecutive tracks can be played back to back
+/// without a gap.
+pub struct SampleReader {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    /// unit of the packet timestamps, `None` if they count frames
+    time_base: Option<TimeBase>,
+    sample_rate: u32,
+    channels: usize,
+    buffer: Option<SampleBuffer<f32>>,
+    /// encoder delay symphonia doesn't handle itself, from an `iTunSMPB` tag
+    itunes_gapless: Option<ItunesGapless>,
+    /// frames still to be dropped from the start of the stream
+    skip: u64,
+    /// timestamp a seek asked for. the demuxer lands on a packet at or before it, the
+    /// frames in between are dropped once that packet is decoded.
+    seek_to: Option<u64>,
+    /// frames left before the padding starts, `None` if unknown
+    remaining: Option<u64>,
+    /// length of the audio without delay and padding
+    frames: Option<u64>,
+}
+
+/// gapless info iTunes (and some other encoders) store in an `iTunSMPB` tag
+#[derive(Debug, Clone, Copy)]
+struct ItunesGapless {
+    delay: u64,
+    frames: u64,
+}
+
+impl ItunesGapless {
+    /// parse a value like ` 00000000 00000840 000001CA 00000000003F31F6 ...`. the fields
+    /// after the first are the delay, the padding and the real number of frames, in hex.
+    fn parse(value: &str) -> Option<ItunesGapless> {
+        let mut fields = value
+            .split_whitespace()
+            .map(|f| u64::from_str_radix(f, 16).ok());
+        let _ = fields.next()?;
+        let delay = fields.next()??;
+        let _padding = fields.next()??;
+        let frames = fields.next()??;
+        (frames > 0).then_some(ItunesGapless { delay, frames })
+    }
+
+    fn find(revision: &MetadataRevision) -> Option<ItunesGapless> {
+        revision
+            .tags()
+            .iter()
+            .filter(|tag| tag.key.to_lowercase().contains("itunsmpb"))
+            .find_map(|tag| ItunesGapless::parse(&tag.value.to_string()))
+    }
+}
+
+impl SampleReader {
+    /// open `path` and prepare a decoder for its default track
+    pub fn open<T: AsRef<Path>>(path: T) -> Result<SampleReader, Error> {
+        let mut probed = probe(path.as_ref())?;
+        let mut format = probed.format;
+        let track = format
+            .default_track()
+            .ok_or(Error::Unsupported("no default track"))?;
+        let track_id = track.id;
+        let params = track.codec_params.clone();
+        let sample_rate = params
+            .sample_rate
+            .ok_or(Error::Unsupported("unknown sample rate"))?;
+        let channels = params.channels.map(|c| c.count()).unwrap_or(2);
+        let decoder = symphonia::default::get_codecs().make(&params, &DecoderOptions::default())?;
+
+        // symphonia trims lame/xing delay itself and reports it here. mp4 aac and itunes
+        // encoded mp3s only have the iTunSMPB tag, which we have to apply ourselves.
+        let itunes_gapless = if params.delay.is_some() {
+            None
+        } else {
+            let container = format.metadata().current().and_then(ItunesGapless::find);
+            let probed_meta = probed
+                .metadata
+                .get()
+                .and_then(|m| m.current().and_then(ItunesGapless::find));
+            container.or(probed_meta)
+        };
+        let frames = match itunes_gapless {
+            Some(gapless) => Some(gapless.frames),
+            None => params.n_frames,
+        };
+
+        Ok(SampleReader {
+            format,
+            decoder,
+            track_id,
+            time_base: params.time_base,
+            sample_rate,
+            channels,
+            buffer: None,
+            itunes_gapless,
+            skip: itunes_gapless.map_or(0, |g| g.delay),
+            seek_to: None,
+            remaining: itunes_gapless.map(|g| g.frames),
+            frames,
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// length of the track in frames, if the container says
+    pub fn frames(&self) -> Option<u64> {
+        self.frames
+    }
+
+    /// length of the track, if the container says
+    pub fn duration(&self) -> Option<Duration> {
+        self.frames
+            .map(|f| Duration::from_secs_f64(f as f64 / self.sample_rate as f64))
+    }
+
+    /// jump to `pos`, down to the frame. returns where playback carries on from, which is
+    /// `pos`, or the end if that's past it and the length is known. streams that can't seek,
+    /// or only forward, give a `SeekError` and play on from where they were.
+    pub fn seek(&mut self, pos: Duration) -> Result<Duration, Error> {
+        let pos = self.duration().map_or(pos, |duration| pos.min(duration));
+        // timestamps count the itunes encoder delay as well
+        let delay = self.itunes_gapless.map_or(0, |g| g.delay);
+        let time = pos.as_secs_f64() + delay as f64 / self.sample_rate as f64;
+        let seeked = self.format.seek(
+            SeekMode::Accurate,
+            SeekTo::TimeStamp {
+                ts: self.timestamp(time),
+                track_id: self.track_id,
+            },
+        )?;
+        self.decoder.reset();
+        let target = seeked.required_ts.max(seeked.actual_ts);
+        self.seek_to = Some(target);
+        let frame = self.ts_frames(target).saturating_sub(delay);
+        if let Some(gapless) = self.itunes_gapless {
+            self.remaining = Some(gapless.frames.saturating_sub(frame));
+        }
+        Ok(Duration::from_secs_f64(
+            frame as f64 / self.sample_rate as f64,
+        ))
+    }
+
+    /// the packet timestamp `secs` into the track. symphonia rounds down when it works this
+    /// out itself, which is often a frame short.
+    fn timestamp(&self, secs: f64) -> u64 {
+        let (numer, denom) = match self.time_base {
+            Some(time_base) => (time_base.numer, time_base.denom),
+            None => (1, self.sample_rate),
+        };
+        (secs * denom as f64 / numer as f64).round() as u64
+    }
+
+    /// number of frames `ts` packet timestamps make up
+    fn ts_frames(&self, ts: u64) -> u64 {
+        match self.time_base {
+            Some(time_base) => {
+                let time = time_base.calc_time(ts);
+                ((time.seconds as f64 + time.frac) * self.sample_rate as f64).round() as u64
+            }
+            None => ts,
+        }
+    }
+
+    /// decode the next packet. returns `Ok(None)` at the end of the stream.
+    /// corrupt packets are skipped rather than ending the stream.
+    pub fn next_block(&mut self) -> Result<Option<&[f32]>, Error> {
+        let (start, end) = loop {
+            if self.remaining == Some(0) {
+                return Ok(None);
+            }
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(Error::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+            // the first packet after a seek starts before where it should, by up to a
+            // packet and whatever the decoder needs to get going again
+            if let Some(target) = self.seek_to {
+                self.skip = self.ts_frames(target.saturating_sub(packet.ts));
+            }
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                Err(Error::DecodeError(_)) => continue,
+                Err(e) => return Err(e),
+            };
+            self.seek_to = None;
+            let spec = *decoded.spec();
+            self.channels = spec.channels.count();
+            let needed = decoded.capacity() * self.channels;
+            if self.buffer.as_ref().is_none_or(|b| b.capacity() < needed) {
+                self.buffer = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+            }
+            let buffer = self.buffer.as_mut().expect("buffer was just created");
+            buffer.copy_interleaved_ref(decoded);
+
+            let frames = (buffer.len() / self.channels) as u64;
+            let skip = self.skip.min(frames);
+            self.skip -= skip;
+            let keep = match &mut self.remaining {
+                Some(remaining) => {
+                    let keep = (frames - skip).min(*remaining);
+                    *remaining -= keep;
+                    keep
+                }
+                None => frames - skip,
+            };
+            if keep == 0 {
+                continue;
+            }
+            let start = skip as usize * self.channels;
+            break (start, start + keep as usize * self.channels);
+        };
+        let buffer = self.buffer.as_ref().expect("a block was just decoded");
+        Ok(Some(&buffer.samples()[start..end]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use hound::{SampleFormat, WavSpec, WavWriter};
+
+    use super::*;
+
+    const FRAMES: u32 = 30000;
+
+    /// a 16 bit stereo WAV with every frame's number in its left channel, so a frame shows
+    /// where it came from
+    fn numbered_wav(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("thump-{}-{}.wav", name, std::process::id()));
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: 44100,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(&path, spec).expect("failed to create test wav");
+        for frame in 0..FRAMES {
+            writer
+                .write_sample(frame as i16)
+                .expect("failed to write test wav");
+            writer
+                .write_sample(-1i16)
+                .expect("failed to write test wav");
+        }
+        writer.finalize().expect("failed to write test wav");
+        path
+    }
+
+    #[test]
+    fn seek_lands_on_the_frame() {
+        let path = numbered_wav("seek");
+        let mut reader = SampleReader::open(&path).expect("failed to open test wav");
+        for frame in [12345, 441, 0, 29999] {
+            let pos = Duration::from_secs_f64(frame as f64 / 44100.0);
+            assert_eq!(reader.seek(pos).expect("seek failed"), pos);
+            let block = reader
+                .next_block()
+                .expect("decode failed")
+                .expect("nothing left");
+            assert_eq!((block[0] * 32768.0).round() as i32, frame);
+            assert_eq!((block[1] * 32768.0).round() as i32, -1);
+        }
+        fs::remove_file(&path).expect("failed to remove test wav");
+    }
+
+    #[test]
+    fn seek_past_the_end_stops_at_the_end() {
+        let path = numbered_wav("seek-end");
+        let mut reader = SampleReader::open(&path).expect("failed to open test wav");
+        let end = reader.duration().expect("wav has a length");
+        let seeked = reader
+            .seek(end + Duration::from_secs(5))
+            .expect("seek failed");
+        assert_eq!(seeked, end);
+        assert!(reader.next_block().expect("decode failed").is_none());
+        fs::remove_file(&path).expect("failed to remove test wav");
+    }
+}
@@ -0,0 +1,314 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// an effect on the audio path, between decoding and output. blocks are interleaved and
+/// can change sample rate or channel count from one call to the next when a track in
+/// another format starts, so effects should check and reset themselves.
+pub trait Dsp: Send {
+    /// name the effect is known by in the ui, in scripts and in the config
+    fn name(&self) -> &'static str;
+    /// process an interleaved block in place
+    fn process(&mut self, block: &mut [f32], sample_rate: u32, channels: usize);
+    /// the parameters that can be changed, with their current values
+    fn params(&self) -> Vec<Param>;
+    /// change a parameter, the value is clamped to its range
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), String>;
+}
+
+/// a parameter of an effect, as shown by the ui
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub value: f32,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Param {
+    fn new(name: &str, value: f32, min: f32, max: f32) -> Param {
+        Param {
+            name: name.to_string(),
+            value,
+            min,
+            max,
+        }
+    }
+}
+
+/// an effect and its parameters, to keep the chain in the config
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EffectSettings {
+    pub name: String,
+    #[serde(default)]
+    pub params: BTreeMap<String, f32>,
+}
+
+/// what an effect in the chain looks like from outside, for the ui
+#[derive(Debug, Clone)]
+pub struct EffectInfo {
+    pub name: String,
+    pub params: Vec<Param>,
+}
+
+impl EffectInfo {
+    pub fn settings(&self) -> EffectSettings {
+        EffectSettings {
+            name: self.name.clone(),
+            params: self
+                .params
+                .iter()
+                .map(|p| (p.name.clone(), p.value))
+                .collect(),
+        }
+    }
+}
+
+/// names of the effects [`create`] knows
+pub const EFFECTS: [&str; 2] = ["crossfeed", "compressor"];
+
+/// a new effect with default parameters
+pub fn create(name: &str) -> Option<Box<dyn Dsp>> {
+    match name {
+        "crossfeed" => Some(Box::new(Crossfeed::default())),
+        "compressor" => Some(Box::new(Compressor::default())),
+        _ => None,
+    }
+}
+
+/// changes to the chain, sent to the engine
+#[derive(Debug, Clone)]
+pub enum DspCommand {
+    /// add an effect by name at the end of the chain
+    Add(String),
+    Remove(usize),
+    /// move the effect at the first index to the second
+    Move(usize, usize),
+    /// set a parameter of the effect at this index
+    SetParam(usize, String, f32),
+}
+
+/// the effects run one after the other, in order
+#[derive(Default)]
+pub struct DspChain {
+    effects: Vec<Box<dyn Dsp>>,
+}
+
+impl DspChain {
+    /// build a chain from the config. unknown effects and parameters are reported and left out.
+    pub fn new(settings: &[EffectSettings]) -> DspChain {
+        let mut chain = DspChain::default();
+        for effect in settings {
+            let Some(mut dsp) = create(&effect.name) else {
+                println!("error: unknown effect {:?}", effect.name);
+                continue;
+            };
+            for (name, value) in &effect.params {
+                if let Err(e) = dsp.set_param(name, *value) {
+                    println!("error: {}", e);
+                }
+            }
+            chain.effects.push(dsp);
+        }
+        chain
+    }
+
+    pub fn process(&mut self, block: &mut [f32], sample_rate: u32, channels: usize) {
+        for effect in &mut self.effects {
+            effect.process(block, sample_rate, channels);
+        }
+    }
+
+    pub fn apply(&mut self, command: DspCommand) -> Result<(), String> {
+        let len = self.effects.len();
+        let check = |index: usize| {
+            (index < len)
+                .then_some(index)
+                .ok_or(format!("no effect at {} in a chain of {}", index, len))
+        };
+        match command {
+            DspCommand::Add(name) => {
+                let dsp = create(&name).ok_or(format!("unknown effect {:?}", name))?;
+                self.effects.push(dsp);
+            }
+            DspCommand::Remove(index) => {
+                self.effects.remove(check(index)?);
+            }
+            DspCommand::Move(from, to) => {
+                let dsp = self.effects.remove(check(from)?);
+                self.effects.insert(to.min(len - 1), dsp);
+            }
+            DspCommand::SetParam(index, name, value) => {
+                self.effects[check(index)?].set_param(&name, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn describe(&self) -> Vec<EffectInfo> {
+        self.effects
+            .iter()
+            .map(|effect| EffectInfo {
+                name: effect.name().to_string(),
+                params: effect.params(),
+            })
+            .collect()
+    }
+}
+
+/// look `name` up in `params` and clamp `value` to its range
+fn clamp_param(params: &[Param], effect: &str, name: &str, value: f32) -> Result<f32, String> {
+    let param = params
+        .iter()
+        .find(|p| p.name == name)
+        .ok_or(format!("{} has no parameter {:?}", effect, name))?;
+    Ok(value.clamp(param.min, param.max))
+}
+
+/// one pole low pass coefficient for a cutoff frequency
+fn one_pole(cutoff: f32, sample_rate: u32) -> f32 {
+    1.0 - (-2.0 * std::f32::consts::PI * cutoff / sample_rate as f32).exp()
+}
+
+/// lets each ear hear a bit of the other channel, low passed like the head does with
+/// speakers, so hard panned mixes are easier on headphones. only touches stereo.
+pub struct Crossfeed {
+    /// how much of the other channel is mixed in, 0 to 1
+    amount: f32,
+    /// cutoff of the low pass on the crossed over signal
+    cutoff: f32,
+    lowpassed: [f32; 2],
+    /// sample rate and channel count of the last block, the low pass starts over when
+    /// they change
+    format: (u32, usize),
+}
+
+impl Default for Crossfeed {
+    fn default() -> Self {
+        Crossfeed {
+            amount: 0.3,
+            cutoff: 700.0,
+            lowpassed: [0.0; 2],
+            format: (0, 0),
+        }
+    }
+}
+
+impl Dsp for Crossfeed {
+    fn name(&self) -> &'static str {
+        "crossfeed"
+    }
+
+    fn process(&mut self, block: &mut [f32], sample_rate: u32, channels: usize) {
+        if (sample_rate, channels) != self.format {
+            self.format = (sample_rate, channels);
+            self.lowpassed = [0.0; 2];
+        }
+        if channels != 2 {
+            return;
+        }
+        let coefficient = one_pole(self.cutoff, sample_rate);
+        // keep a centred signal at the same level
+        let normalize = 1.0 / (1.0 + self.amount);
+        for frame in block.chunks_exact_mut(2) {
+            for (lowpassed, sample) in self.lowpassed.iter_mut().zip(frame.iter()) {
+                *lowpassed += coefficient * (sample - *lowpassed);
+            }
+            let (left, right) = (frame[0], frame[1]);
+            frame[0] = (left + self.amount * self.lowpassed[1]) * normalize;
+            frame[1] = (right + self.amount * self.lowpassed[0]) * normalize;
+        }
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![
+            Param::new("amount", self.amount, 0.0, 1.0),
+            Param::new("cutoff", self.cutoff, 200.0, 2000.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+        let value = clamp_param(&self.params(), self.name(), name, value)?;
+        match name {
+            "amount" => self.amount = value,
+            _ => self.cutoff = value,
+        }
+        Ok(())
+    }
+}
+
+/// evens out the level by turning loud parts down. all channels are compressed together
+/// so the stereo image doesn't wander.
+pub struct Compressor {
+    /// level in dB above which the gain is reduced
+    threshold: f32,
+    ratio: f32,
+    /// ms to react to a louder signal
+    attack: f32,
+    /// ms to recover once it gets quieter
+    release: f32,
+    /// dB added after compressing, to make up for the lost level
+    makeup: f32,
+    /// current gain reduction in dB
+    reduction: f32,
+}
+
+impl Default for Compressor {
+    fn default() -> Self {
+        Compressor {
+            threshold: -18.0,
+            ratio: 4.0,
+            attack: 10.0,
+            release: 150.0,
+            makeup: 0.0,
+            reduction: 0.0,
+        }
+    }
+}
+
+impl Dsp for Compressor {
+    fn name(&self) -> &'static str {
+        "compressor"
+    }
+
+    fn process(&mut self, block: &mut [f32], sample_rate: u32, channels: usize) {
+        let smoothing = |ms: f32| (-1000.0 / (ms * sample_rate as f32)).exp();
+        let (attack, release) = (smoothing(self.attack), smoothing(self.release));
+        for frame in block.chunks_exact_mut(channels) {
+            let peak = frame.iter().fold(0.0f32, |max, s| max.max(s.abs()));
+            let level = 20.0 * peak.max(1e-9).log10();
+            let over = (level - self.threshold).max(0.0);
+            let target = over - over / self.ratio;
+            let coefficient = if target > self.reduction {
+                attack
+            } else {
+                release
+            };
+            self.reduction = target + coefficient * (self.reduction - target);
+            let gain = 10f32.powf((self.makeup - self.reduction) / 20.0);
+            frame.iter_mut().for_each(|s| *s *= gain);
+        }
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![
+            Param::new("threshold", self.threshold, -60.0, 0.0),
+            Param::new("ratio", self.ratio, 1.0, 20.0),
+            Param::new("attack", self.attack, 0.1, 100.0),
+            Param::new("release", self.release, 10.0, 1000.0),
+            Param::new("makeup", self.makeup, 0.0, 24.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+        let value = clamp_param(&self.params(), self.name(), name, value)?;
+        match name {
+            "threshold" => self.threshold = value,
+            "ratio" => self.ratio = value,
+            "attack" => self.attack = value,
+            "release" => self.release = value,
+            _ => self.makeup = value,
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use iced::{
+    futures::{SinkExt, Stream},
+    stream,
+};
+use lofty::file::{AudioFile, FileType, TaggedFile, TaggedFileExt};
+
+use crate::decode::{self, SampleReader};
+
+/// how far into an mp3 file, after the id3 tag, the first frame is looked for
+const FIRST_FRAME_SEARCH: usize = 64 * 1024;
+
+/// the length of a file as far as it can be told without reading all of it. the container
+/// says for most formats. mp3 only knows its length if there is a Xing or VBRI header, lofty
+/// and symphonia guess from the bitrate otherwise, which is off for VBR files. `None` means
+/// [`scan`] has to count the packets.
+pub fn probe(path: &Path, file: &TaggedFile) -> Option<Duration> {
+    if file.file_type() == FileType::Mpeg {
+        return vbr_header(path);
+    }
+    let duration = file.properties().duration();
+    if !duration.is_zero() {
+        return Some(duration);
+    }
+    SampleReader::open(path).ok()?.duration()
+}
+
+/// the length of a file from counting its packets, for when [`probe`] can't tell
+pub fn scan(path: &Path) -> Result<Duration, String> {
+    decode::count_packets(path).map_err(|e| e.to_string())
+}
+
+/// scan every file on the blocking pool, one at a time, yielding the results as they come
+pub fn scan_all(paths: Vec<PathBuf>) -> impl Stream<Item = (PathBuf, Result<Duration, String>)> {
+    stream::channel(16, move |mut output| async move {
+        for path in paths {
+            let job_path = path.clone();
+            let result = tokio::task::spawn_blocking(move || scan(&job_path))
+                .await
+                .unwrap_or_else(|e| Err(format!("scan panicked: {}", e)));
+            if output.send((path, result)).await.is_err() {
+                return;
+            }
+        }
+    })
+}
+
+/// what the first frame header of an mp3 says
+struct FrameHeader {
+    mpeg1: bool,
+    mono: bool,
+    sample_rate: u32,
+    /// frames of audio in every mpeg frame
+    samples: u64,
+}
+
+impl FrameHeader {
+    fn parse(bytes: &[u8]) -> Option<FrameHeader> {
+        if bytes.len() < 4 || bytes[0] != 0xff || bytes[1] & 0xe0 != 0xe0 {
+            return None;
+        }
+        let version = (bytes[1] >> 3) & 3;
+        let layer = (bytes[1] >> 1) & 3;
+        let bitrate = bytes[2] >> 4;
+        let rate = (bytes[2] >> 2) & 3;
+        if version == 1 || layer == 0 || bitrate == 0 || bitrate == 15 || rate == 3 {
+            return None;
+        }
+        let mpeg1 = version == 3;
+        let base = [44100, 48000, 32000][rate as usize];
+        let sample_rate = match version {
+            3 => base,
+            2 => base / 2,
+            _ => base / 4,
+        };
+        let samples = match layer {
+            3 => 384,
+            2 => 1152,
+            _ if mpeg1 => 1152,
+            _ => 576,
+        };
+        Some(FrameHeader {
+            mpeg1,
+            mono: bytes[3] >> 6 == 3,
+            sample_rate,
+            samples,
+        })
+    }
+}
+
+/// the length of an mp3 from the Xing (or Info) header LAME and most encoders put in the
+/// first frame, or the VBRI header of the Fraunhofer encoder
+fn vbr_header(path: &Path) -> Option<Duration> {
+    let mut file = File::open(path).ok()?;
+    let mut head = [0; 10];
+    file.read_exact(&mut head).ok()?;
+    let mut bytes = Vec::new();
+    if &head[..3] == b"ID3" {
+        // the tag comes first, the frames after it
+        let mut tag = vec![0; id3_size(&head)];
+        file.read_exact(&mut tag).ok()?;
+    } else {
+        bytes.extend(head);
+    }
+    file.take(FIRST_FRAME_SEARCH as u64)
+        .read_to_end(&mut bytes)
+        .ok()?;
+
+    let start = (0..bytes.len()).find(|&i| FrameHeader::parse(&bytes[i..]).is_some())?;
+    let frame = &bytes[start..];
+    let header = FrameHeader::parse(frame)?;
+    let side_info = match (header.mpeg1, header.mono) {
+        (true, false) => 32,
+        (true, true) | (false, false) => 17,
+        (false, true) => 9,
+    };
+    let (frames, trim) = xing(frame.get(4 + side_info..)?).or_else(|| vbri(frame.get(36..)?))?;
+    let samples = (frames * header.samples).saturating_sub(trim);
+    Some(Duration::from_secs_f64(
+        samples as f64 / header.sample_rate as f64,
+    ))
+}
+
+/// size of an id3v2 tag after its 10 byte header, including the footer if there is one
+fn id3_size(head: &[u8; 10]) -> usize {
+    let size = head[6..10]
+        .iter()
+        .fold(0, |size, &b| (size << 7) | (b & 0x7f) as usize);
+    let footer = if head[5] & 0x10 != 0 { 10 } else { 0 };
+    size + footer
+}
+
+/// number of mpeg frames in a Xing header, and the encoder delay and padding from the LAME
+/// extension after it, in frames of audio
+fn xing(bytes: &[u8]) -> Option<(u64, u64)> {
+    if !bytes.starts_with(b"Xing") && !bytes.starts_with(b"Info") {
+        return None;
+    }
+    let flags = u32::from_be_bytes(bytes.get(4..8)?.try_into().ok()?);
+    if flags & 1 == 0 {
+        return None;
+    }
+    let frames = u32::from_be_bytes(bytes.get(8..12)?.try_into().ok()?) as u64;
+    // frame count, byte count, table of contents and quality, whichever are there
+    let lame = 12
+        + [0, 4, 100, 4]
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| flags & (1 << bit) != 0)
+            .map(|(_, len)| len)
+            .sum::<usize>();
+    let trim = match bytes.get(lame..lame + 24) {
+        Some(ext)
+            if [b"LAME", b"Lavf", b"Lavc"]
+                .iter()
+                .any(|e| ext.starts_with(*e)) =>
+        {
+            let delay = ((ext[21] as u64) << 4) | (ext[22] as u64 >> 4);
+            let padding = ((ext[22] as u64 & 0xf) << 8) | ext[23] as u64;
+            delay + padding
+        }
+        _ => 0,
+    };
+    Some((frames, trim))
+}
+
+/// number of mpeg frames in a VBRI header
+fn vbri(bytes: &[u8]) -> Option<(u64, u64)> {
+    if !bytes.starts_with(b"VBRI") {
+        return None;
+    }
+    let frames = u32::from_be_bytes(bytes.get(14..18)?.try_into().ok()?) as u64;
+    Some((frames, 0))
+}
@@ -0,0 +1,385 @@
+use std::{f64::consts::PI, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+/// centre frequencies of the graphic equalizer, one octave apart
+pub const GRAPHIC_BANDS: [f32; 10] = [
+    31.25, 62.5, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0,
+];
+/// gains of the graphic bands go this far up and down, in dB
+pub const GRAPHIC_RANGE: f32 = 12.0;
+/// q of the graphic bands, about one octave wide
+const GRAPHIC_Q: f32 = 1.41;
+/// after a change the old and new filters are crossfaded over this long, so moving a
+/// slider mid-song doesn't click
+const CHANGE_FADE_SECONDS: f64 = 0.02;
+/// chains making up less than this of a fade are dropped, well below anything audible
+const MIN_FADE_WEIGHT: f32 = 0.001;
+
+/// a second order iir filter in transposed direct form II
+#[derive(Debug, Clone, Copy)]
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    /// a filter from coefficients already divided by `a0`
+    pub fn new(b: [f64; 3], a: [f64; 2]) -> Biquad {
+        Biquad { b, a, z: [0.0; 2] }
+    }
+
+    /// peaking, low shelf or high shelf filter as in the RBJ audio eq cookbook
+    fn from_band(band: &EqBand, sample_rate: u32) -> Biquad {
+        let rate = sample_rate as f64;
+        let freq = (band.freq as f64).clamp(1.0, rate * 0.49);
+        let gain = 10f64.powf(band.gain as f64 / 40.0);
+        let w0 = 2.0 * PI * freq / rate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * (band.q as f64).max(0.01));
+        let shelf = 2.0 * gain.sqrt() * alpha;
+        let (b, a0, a) = match band.kind {
+            FilterKind::Peaking => (
+                [1.0 + alpha * gain, -2.0 * cos, 1.0 - alpha * gain],
+                1.0 + alpha / gain,
+                [-2.0 * cos, 1.0 - alpha / gain],
+            ),
+            FilterKind::LowShelf => (
+                [
+                    gain * ((gain + 1.0) - (gain - 1.0) * cos + shelf),
+                    2.0 * gain * ((gain - 1.0) - (gain + 1.0) * cos),
+                    gain * ((gain + 1.0) - (gain - 1.0) * cos - shelf),
+                ],
+                (gain + 1.0) + (gain - 1.0) * cos + shelf,
+                [
+                    -2.0 * ((gain - 1.0) + (gain + 1.0) * cos),
+                    (gain + 1.0) + (gain - 1.0) * cos - shelf,
+                ],
+            ),
+            FilterKind::HighShelf => (
+                [
+                    gain * ((gain + 1.0) + (gain - 1.0) * cos + shelf),
+                    -2.0 * gain * ((gain - 1.0) + (gain + 1.0) * cos),
+                    gain * ((gain + 1.0) + (gain - 1.0) * cos - shelf),
+                ],
+                (gain + 1.0) - (gain - 1.0) * cos + shelf,
+                [
+                    2.0 * ((gain - 1.0) - (gain + 1.0) * cos),
+                    (gain + 1.0) - (gain - 1.0) * cos - shelf,
+                ],
+            ),
+        };
+        Biquad::new(b.map(|b| b / a0), a.map(|a| a / a0))
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FilterKind {
+    Peaking,
+    LowShelf,
+    HighShelf,
+}
+
+/// one filter of a parametric equalizer
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct EqBand {
+    pub kind: FilterKind,
+    /// centre (or corner, for shelves) frequency in Hz
+    pub freq: f32,
+    /// in dB
+    pub gain: f32,
+    pub q: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum EqMode {
+    /// a gain in dB for each of the [`GRAPHIC_BANDS`]
+    Graphic {
+        gains: [f32; 10],
+    },
+    Parametric {
+        bands: Vec<EqBand>,
+    },
+}
+
+/// the preamp that leaves room for the biggest boost of a graphic preset, so it can't clip
+pub fn graphic_preamp(gains: &[f32; 10]) -> f32 {
+    -gains.iter().copied().fold(0.0, f32::max)
+}
+
+/// a named set of equalizer settings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EqPreset {
+    pub name: String,
+    /// gain in dB applied before the filters, usually negative to make room for boosts
+    #[serde(default)]
+    pub preamp: f32,
+    #[serde(flatten)]
+    pub mode: EqMode,
+}
+
+impl EqPreset {
+    fn graphic(name: &str, gains: [f32; 10]) -> EqPreset {
+        EqPreset {
+            name: name.to_string(),
+            preamp: graphic_preamp(&gains),
+            mode: EqMode::Graphic { gains },
+        }
+    }
+
+    /// the filters this preset boils down to
+    pub fn bands(&self) -> Vec<EqBand> {
+        match &self.mode {
+            EqMode::Graphic { gains } => GRAPHIC_BANDS
+                .iter()
+                .zip(gains)
+                .filter(|(_, gain)| **gain != 0.0)
+                .map(|(freq, gain)| EqBand {
+                    kind: FilterKind::Peaking,
+                    freq: *freq,
+                    gain: gain.clamp(-GRAPHIC_RANGE, GRAPHIC_RANGE),
+                    q: GRAPHIC_Q,
+                })
+                .collect(),
+            EqMode::Parametric { bands } => bands.clone(),
+        }
+    }
+}
+
+/// the presets that are always there
+pub fn builtin_presets() -> Vec<EqPreset> {
+    vec![
+        EqPreset::graphic("flat", [0.0; 10]),
+        EqPreset::graphic(
+            "bass boost",
+            [6.0, 5.0, 3.5, 1.5, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+        ),
+        EqPreset::graphic(
+            "treble boost",
+            [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.5, 3.0, 4.5, 5.5],
+        ),
+        EqPreset::graphic(
+            "vocal",
+            [-2.0, -1.5, -1.0, 0.0, 2.0, 3.0, 3.0, 1.5, 0.0, -1.0],
+        ),
+        EqPreset::graphic(
+            "loudness",
+            [5.0, 4.0, 2.0, 0.0, -1.0, 0.0, 0.0, 1.0, 3.0, 4.0],
+        ),
+    ]
+}
+
+/// read a parametric eq in the format AutoEQ publishes (`ParametricEQ.txt`), like
+///
+/// ```text
+/// Preamp: -6.4 dB
+/// Filter 1: ON LSC Fc 105 Hz Gain 6.0 dB Q 0.70
+/// Filter 2: ON PK Fc 170 Hz Gain -2.8 dB Q 0.60
+/// ```
+///
+/// the preset is named after the file
+pub fn import_autoeq(path: &Path) -> Result<EqPreset, String> {
+    let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let mut preamp = 0.0;
+    let mut bands = Vec::new();
+    for line in contents.lines() {
+        let line = line.trim();
+        if let Some(value) = line.strip_prefix("Preamp:") {
+            preamp = parse_number(value).ok_or(format!("bad preamp line {:?}", line))?;
+        } else if line.starts_with("Filter") {
+            if let Some(band) = parse_autoeq_filter(line)? {
+                bands.push(band);
+            }
+        }
+    }
+    if bands.is_empty() {
+        return Err("no filters found".to_string());
+    }
+    let name = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "autoeq".to_string());
+    Ok(EqPreset {
+        name,
+        preamp,
+        mode: EqMode::Parametric { bands },
+    })
+}
+
+fn parse_number(value: &str) -> Option<f32> {
+    value.split_whitespace().next()?.parse().ok()
+}
+
+/// one `Filter n: ON PK Fc 105 Hz Gain 6.0 dB Q 0.70` line. filters that are off, and
+/// kinds we have no filter for, give `None`.
+fn parse_autoeq_filter(line: &str) -> Result<Option<EqBand>, String> {
+    let tokens: Vec<&str> = line
+        .split_once(':')
+        .map(|(_, rest)| rest)
+        .unwrap_or_default()
+        .split_whitespace()
+        .collect();
+    if tokens.first() != Some(&"ON") {
+        return Ok(None);
+    }
+    let kind = match tokens.get(1) {
+        Some(&"PK") | Some(&"PEQ") => FilterKind::Peaking,
+        Some(&"LSC") | Some(&"LS") => FilterKind::LowShelf,
+        Some(&"HSC") | Some(&"HS") => FilterKind::HighShelf,
+        _ => return Ok(None),
+    };
+    let value = |key: &str| {
+        tokens
+            .iter()
+            .position(|t| *t == key)
+            .and_then(|i| tokens.get(i + 1))
+            .and_then(|v| v.parse::<f32>().ok())
+    };
+    let freq = value("Fc").ok_or(format!("no frequency in {:?}", line))?;
+    Ok(Some(EqBand {
+        kind,
+        freq,
+        gain: value("Gain").unwrap_or(0.0),
+        // shelves without a q get the usual 0.71
+        q: value("Q").unwrap_or(0.71),
+    }))
+}
+
+/// the filters of one preset, set up for a sample rate and channel count
+struct Chain {
+    preamp: f32,
+    /// one filter per band and channel
+    filters: Vec<Vec<Biquad>>,
+}
+
+impl Chain {
+    fn new(preset: Option<&EqPreset>, sample_rate: u32, channels: usize) -> Chain {
+        match preset {
+            Some(preset) => Chain {
+                preamp: 10f32.powf(preset.preamp / 20.0),
+                filters: preset
+                    .bands()
+                    .iter()
+                    .map(|band| vec![Biquad::from_band(band, sample_rate); channels])
+                    .collect(),
+            },
+            None => Chain {
+                preamp: 1.0,
+                filters: Vec::new(),
+            },
+        }
+    }
+
+    fn process(&mut self, channel: usize, sample: f32) -> f32 {
+        self.filters
+            .iter_mut()
+            .fold((sample * self.preamp) as f64, |x, band| {
+                band[channel].process(x)
+            }) as f32
+    }
+}
+
+/// the equalizer on the audio path. `None` as the preset bypasses it.
+pub struct Equalizer {
+    preset: Option<EqPreset>,
+    sample_rate: u32,
+    channels: usize,
+    chain: Chain,
+    /// what was heard before the last change, faded out over the first frames after it.
+    /// the chains are mixed by their weights, there is more than one when the settings
+    /// changed again before the last fade was done.
+    fading: Vec<(Chain, f32)>,
+    /// frames into that fade
+    fade_pos: usize,
+}
+
+impl Equalizer {
+    pub fn new(preset: Option<EqPreset>) -> Equalizer {
+        Equalizer {
+            preset,
+            sample_rate: 0,
+            channels: 0,
+            chain: Chain::new(None, 0, 0),
+            fading: Vec::new(),
+            fade_pos: 0,
+        }
+    }
+
+    /// switch to `preset`, fading over from the old settings
+    pub fn set(&mut self, preset: Option<EqPreset>) {
+        if preset == self.preset {
+            return;
+        }
+        self.preset = preset;
+        let chain = Chain::new(self.preset.as_ref(), self.sample_rate, self.channels);
+        let old = std::mem::replace(&mut self.chain, chain);
+        // fade from what is heard right now, which is partly the last fade if that isn't
+        // done yet
+        let t = self.fade_progress();
+        for (_, weight) in &mut self.fading {
+            *weight *= 1.0 - t;
+        }
+        self.fading.retain(|(_, weight)| *weight >= MIN_FADE_WEIGHT);
+        self.fading.push((old, t));
+        self.fade_pos = 0;
+    }
+
+    fn fade_frames(&self) -> usize {
+        (CHANGE_FADE_SECONDS * self.sample_rate as f64) as usize
+    }
+
+    /// how far the current chain has faded in, 0 to 1
+    fn fade_progress(&self) -> f32 {
+        if self.fading.is_empty() || self.fade_pos >= self.fade_frames() {
+            1.0
+        } else {
+            self.fade_pos as f32 / self.fade_frames() as f32
+        }
+    }
+
+    /// filter an interleaved block in place
+    pub fn process(&mut self, block: &mut [f32], sample_rate: u32, channels: usize) {
+        if sample_rate != self.sample_rate || channels != self.channels {
+            // a new track in another format, nothing to fade from
+            self.sample_rate = sample_rate;
+            self.channels = channels;
+            self.chain = Chain::new(self.preset.as_ref(), sample_rate, channels);
+            self.fading.clear();
+        }
+        if self.fading.is_empty() && self.chain.filters.is_empty() && self.chain.preamp == 1.0 {
+            return;
+        }
+        for frame in block.chunks_exact_mut(channels) {
+            if self.fading.is_empty() {
+                for (channel, sample) in frame.iter_mut().enumerate() {
+                    *sample = self.chain.process(channel, *sample);
+                }
+                continue;
+            }
+            let t = self.fade_progress();
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                let new = self.chain.process(channel, *sample);
+                let old: f32 = self
+                    .fading
+                    .iter_mut()
+                    .map(|(chain, weight)| chain.process(channel, *sample) * *weight)
+                    .sum();
+                *sample = new * t + old * (1.0 - t);
+            }
+            self.fade_pos += 1;
+            if self.fade_pos >= self.fade_frames() {
+                self.fading.clear();
+            }
+        }
+    }
+}
@@ -0,0 +1,358 @@
+/// frames per FLAC frame, what `flac` itself uses at its default level
+pub const BLOCK_SIZE: usize = 4096;
+/// highest order of the fixed predictors
+const MAX_ORDER: usize = 4;
+/// largest rice parameter, 15 would mean the partition is escaped
+const MAX_RICE: u32 = 14;
+
+/// `fLaC` and the stream info block, what a decoder needs before the first frame. the length
+/// of the stream is left unknown, so frames can keep coming for as long as there is audio.
+pub fn stream_header(sample_rate: u32) -> Vec<u8> {
+    let mut out = BitWriter::default();
+    out.bytes.extend(b"fLaC");
+    // last metadata block, type 0 (stream info), 34 bytes
+    out.put(1, 1);
+    out.put(0, 7);
+    out.put(34, 24);
+    out.put(BLOCK_SIZE as u64, 16);
+    out.put(BLOCK_SIZE as u64, 16);
+    // smallest and largest frame size, not known
+    out.put(0, 24);
+    out.put(0, 24);
+    out.put(sample_rate as u64, 20);
+    out.put(1, 3);
+    out.put(15, 5);
+    // total number of frames and the md5 of the audio, not known either
+    out.put(0, 36);
+    out.bytes.extend([0; 16]);
+    out.bytes
+}
+
+/// encodes interleaved stereo to 16 bit FLAC frames. it only uses the fixed predictors with a
+/// single rice partition, which is quick and gets most of the way to what `flac` does.
+pub struct Encoder {
+    sample_rate: u32,
+    /// number of the next frame, part of its header
+    frame: u64,
+}
+
+impl Encoder {
+    pub fn new(sample_rate: u32) -> Encoder {
+        Encoder {
+            sample_rate,
+            frame: 0,
+        }
+    }
+
+    /// one frame of up to 65536 interleaved stereo frames
+    pub fn frame(&mut self, samples: &[f32]) -> Vec<u8> {
+        let quantize = |s: f32| (s.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i32;
+        let left: Vec<i32> = samples.iter().step_by(2).map(|&s| quantize(s)).collect();
+        let right: Vec<i32> = samples
+            .iter()
+            .skip(1)
+            .step_by(2)
+            .map(|&s| quantize(s))
+            .collect();
+        let len = left.len();
+        let mid: Vec<i32> = left.iter().zip(&right).map(|(l, r)| (l + r) >> 1).collect();
+        let side: Vec<i32> = left.iter().zip(&right).map(|(l, r)| l - r).collect();
+
+        // keep the channels apart or store them as mid and side, whichever is smaller
+        let apart = [Subframe::plan(&left, 16), Subframe::plan(&right, 16)];
+        let joint = [Subframe::plan(&mid, 16), Subframe::plan(&side, 17)];
+        let size = |s: &[Subframe; 2]| s[0].bits + s[1].bits;
+        let (assignment, subframes, channels) = if size(&joint) < size(&apart) {
+            (0b1010, joint, [&mid, &side])
+        } else {
+            (0b0001, apart, [&left, &right])
+        };
+
+        let mut out = BitWriter::default();
+        out.put(0xfff8, 16);
+        out.put(if len == BLOCK_SIZE { 0b1100 } else { 0b0111 }, 4);
+        out.put(rate_code(self.sample_rate), 4);
+        out.put(assignment, 4);
+        out.put(0b100, 3);
+        out.put(0, 1);
+        out.put_utf8(self.frame);
+        if len != BLOCK_SIZE {
+            out.put(len as u64 - 1, 16);
+        }
+        out.put(crc8(&out.bytes) as u64, 8);
+        for (subframe, samples) in subframes.iter().zip(channels) {
+            subframe.write(samples, &mut out);
+        }
+        out.align();
+        out.put(crc16(&out.bytes) as u64, 16);
+        self.frame += 1;
+        out.bytes
+    }
+}
+
+/// the header code of the common sample rates, the rest is taken from the stream info
+fn rate_code(sample_rate: u32) -> u64 {
+    match sample_rate {
+        22050 => 0b0111,
+        32000 => 0b1000,
+        44100 => 0b1001,
+        48000 => 0b1010,
+        96000 => 0b1011,
+        _ => 0,
+    }
+}
+
+/// how one channel of a frame is stored
+struct Subframe {
+    bits_per_sample: u32,
+    /// every sample is the same, as in silence
+    constant: bool,
+    order: usize,
+    rice: u32,
+    /// size of the subframe
+    bits: usize,
+}
+
+impl Subframe {
+    /// the fixed predictor that leaves the least to store, and the rice parameter that
+    /// stores it best
+    fn plan(samples: &[i32], bits_per_sample: u32) -> Subframe {
+        let mut plan = Subframe {
+            bits_per_sample,
+            constant: true,
+            order: 0,
+            rice: 0,
+            bits: 8 + bits_per_sample as usize,
+        };
+        if samples.iter().all(|&s| s == samples[0]) {
+            return plan;
+        }
+        plan.constant = false;
+        plan.order = (0..=MAX_ORDER.min(samples.len() - 1))
+            .min_by_key(|&order| {
+                (order..samples.len())
+                    .map(|i| residual(samples, order, i).unsigned_abs())
+                    .sum::<u64>()
+            })
+            .unwrap_or(0);
+        let residuals: Vec<u64> = (plan.order..samples.len())
+            .map(|i| zigzag(residual(samples, plan.order, i)))
+            .collect();
+        let (rice, cost) = (0..=MAX_RICE)
+            .map(|k| {
+                let quotients: u64 = residuals.iter().map(|u| u >> k).sum();
+                (k, quotients as usize + residuals.len() * (k as usize + 1))
+            })
+            .min_by_key(|&(_, cost)| cost)
+            .unwrap_or((0, 0));
+        plan.rice = rice;
+        // header, warm up samples, residual coding method, partition order, parameter
+        plan.bits = 8 + plan.order * bits_per_sample as usize + 10 + cost;
+        plan
+    }
+
+    fn write(&self, samples: &[i32], out: &mut BitWriter) {
+        let bits = self.bits_per_sample;
+        if self.constant {
+            out.put(0, 8);
+            out.put(samples[0] as u64, bits);
+            return;
+        }
+        out.put(0b0001_0000 | (self.order as u64) << 1, 8);
+        for &sample in &samples[..self.order] {
+            out.put(sample as u64, bits);
+        }
+        out.put(0, 2);
+        out.put(0, 4);
+        out.put(self.rice as u64, 4);
+        for i in self.order..samples.len() {
+            let value = zigzag(residual(samples, self.order, i));
+            out.put_unary(value >> self.rice);
+            out.put(value, self.rice);
+        }
+    }
+}
+
+/// what the fixed predictor of `order` leaves over at `i`
+fn residual(x: &[i32], order: usize, i: usize) -> i64 {
+    let x = |back: usize| x[i - back] as i64;
+    match order {
+        0 => x(0),
+        1 => x(0) - x(1),
+        2 => x(0) - 2 * x(1) + x(2),
+        3 => x(0) - 3 * x(1) + 3 * x(2) - x(3),
+        _ => x(0) - 4 * x(1) + 6 * x(2) - 4 * x(3) + x(4),
+    }
+}
+
+/// signed to unsigned, small either way stays small
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ (byte as u16) << 8, |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// writes values of any number of bits, most significant bit first
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    /// bits not making up a whole byte yet, in the low end
+    pending: u64,
+    pending_bits: u32,
+}
+
+impl BitWriter {
+    /// the low `bits` bits of `value`. up to 57, so they fit next to the 7 that can be
+    /// pending
+    fn put(&mut self, value: u64, bits: u32) {
+        let mask = (1u64 << bits) - 1;
+        self.pending = (self.pending << bits) | (value & mask);
+        self.pending_bits += bits;
+        while self.pending_bits >= 8 {
+            self.pending_bits -= 8;
+            self.bytes.push((self.pending >> self.pending_bits) as u8);
+        }
+        self.pending &= (1 << self.pending_bits) - 1;
+    }
+
+    /// `value` zeros and a one
+    fn put_unary(&mut self, mut value: u64) {
+        while value > 32 {
+            self.put(0, 32);
+            value -= 32;
+        }
+        self.put(1, value as u32 + 1);
+    }
+
+    /// the frame number, coded like utf-8 but for up to 36 bits
+    fn put_utf8(&mut self, value: u64) {
+        if value < 0x80 {
+            self.put(value, 8);
+            return;
+        }
+        // a sequence of `len` bytes holds 5 * len + 1 bits
+        let mut len = 2;
+        while value >> (5 * len + 1) != 0 {
+            len += 1;
+        }
+        let lead = (0xff00u64 >> len) & 0xff;
+        self.put(lead | value >> (6 * (len - 1)), 8);
+        for i in (0..len - 1).rev() {
+            self.put(0x80 | (value >> (6 * i)) & 0x3f, 8);
+        }
+    }
+
+    /// fill up the last byte with zeros
+    fn align(&mut self) {
+        if self.pending_bits > 0 {
+            self.put(0, 8 - self.pending_bits);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use symphonia::core::{
+        audio::SampleBuffer, codecs::DecoderOptions, formats::FormatOptions, io::MediaSourceStream,
+        meta::MetadataOptions, probe::Hint,
+    };
+
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 44100;
+
+    /// interleaved stereo with a tone of `left_hz` on the left and `right_hz` on the right
+    fn tone(frames: usize, left_hz: f32, right_hz: f32) -> Vec<f32> {
+        let sine = |hz: f32, i: usize| {
+            0.5 * (2.0 * std::f32::consts::PI * hz * i as f32 / SAMPLE_RATE as f32).sin()
+        };
+        (0..frames)
+            .flat_map(|i| [sine(left_hz, i), sine(right_hz, i)])
+            .collect()
+    }
+
+    /// all the samples in a FLAC stream, as 16 bit
+    fn decode(stream: Vec<u8>) -> Vec<i16> {
+        let mss = MediaSourceStream::new(Box::new(Cursor::new(stream)), Default::default());
+        let mut format = symphonia::default::get_probe()
+            .format(
+                Hint::new().with_extension("flac"),
+                mss,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )
+            .expect("the stream header didn't parse")
+            .format;
+        let params = format
+            .default_track()
+            .expect("no track")
+            .codec_params
+            .clone();
+        let mut decoder = symphonia::default::get_codecs()
+            .make(&params, &DecoderOptions::default())
+            .expect("no flac decoder");
+        let mut samples = Vec::new();
+        while let Ok(packet) = format.next_packet() {
+            let decoded = decoder.decode(&packet).expect("a frame didn't decode");
+            let mut buffer = SampleBuffer::<i16>::new(decoded.capacity() as u64, *decoded.spec());
+            buffer.copy_interleaved_ref(decoded);
+            samples.extend_from_slice(buffer.samples());
+        }
+        samples
+    }
+
+    #[test]
+    fn frames_decode_to_what_went_in() {
+        // enough frames for their numbers to take two bytes, then a silent one for the
+        // constant subframes and a short one like the last of a stream
+        let mut blocks: Vec<Vec<f32>> = (0..130)
+            .map(|i| tone(BLOCK_SIZE, 440.0 + i as f32, 660.0))
+            .collect();
+        blocks.push(vec![0.0; BLOCK_SIZE * 2]);
+        blocks.push(tone(1000, 1000.0, 250.0));
+
+        let mut encoder = Encoder::new(SAMPLE_RATE);
+        let mut stream = stream_header(SAMPLE_RATE);
+        for block in &blocks {
+            stream.extend(encoder.frame(block));
+        }
+        let decoded = decode(stream);
+
+        let expected: Vec<f32> = blocks.concat();
+        assert_eq!(decoded.len(), expected.len());
+        for (i, (&got, &sample)) in decoded.iter().zip(&expected).enumerate() {
+            let want = (sample * i16::MAX as f32).round() as i32;
+            assert!(
+                (got as i32 - want).abs() <= 1,
+                "sample {}: {} for {}",
+                i,
+                got,
+                want
+            );
+        }
+    }
+}
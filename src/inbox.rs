@@ -0,0 +1,194 @@
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use lofty::{
+    config::WriteOptions,
+    file::{AudioFile, TaggedFileExt},
+    read_from_path,
+    tag::{Accessor, ItemKey, Tag},
+};
+
+use crate::{
+    config::{Config, ImportMode},
+    read_files::{list_files, read_song_untagged, ScanRules},
+    template, Song,
+};
+
+/// a tag that can be fixed in the inbox before a file is accepted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TagField {
+    Title,
+    Artist,
+    AlbumArtist,
+    Album,
+    Year,
+    Track,
+    Disc,
+}
+
+impl TagField {
+    pub const ALL: [TagField; 7] = [
+        TagField::Title,
+        TagField::Artist,
+        TagField::AlbumArtist,
+        TagField::Album,
+        TagField::Year,
+        TagField::Track,
+        TagField::Disc,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            TagField::Title => "title",
+            TagField::Artist => "artist",
+            TagField::AlbumArtist => "album artist",
+            TagField::Album => "album",
+            TagField::Year => "year",
+            TagField::Track => "track",
+            TagField::Disc => "disc",
+        }
+    }
+
+    /// the current value of this field on `song`, empty if it isn't set
+    pub fn get(&self, song: &Song) -> String {
+        match self {
+            TagField::Title => song.name.clone().unwrap_or_default(),
+            TagField::Artist => song.track_artist.clone().unwrap_or_default(),
+            TagField::AlbumArtist => song.album_artist.clone().unwrap_or_default(),
+            TagField::Album => song.album_name.clone().unwrap_or_default(),
+            TagField::Year => song.recording_date.clone().unwrap_or_default(),
+            TagField::Track => song.track_number.map(|n| n.to_string()).unwrap_or_default(),
+            TagField::Disc => song.disc_number.map(|n| n.to_string()).unwrap_or_default(),
+        }
+    }
+
+    /// set this field on `song`. an empty value clears it, numbers that don't parse are dropped
+    pub fn set(&self, song: &mut Song, value: String) {
+        let text = Some(value.clone()).filter(|v| !v.is_empty());
+        let number = value.trim().parse().ok();
+        match self {
+            TagField::Title => song.name = text,
+            TagField::Artist => song.track_artist = text,
+            TagField::AlbumArtist => song.album_artist = text,
+            TagField::Album => song.album_name = text,
+            TagField::Year => song.recording_date = text,
+            TagField::Track => song.track_number = number,
+            TagField::Disc => song.disc_number = number,
+        }
+    }
+}
+
+/// list the downloads folder and read the tags of every file not in `seen`.
+/// returns the files still in the folder that were read, now or before, and the newly
+/// found songs. files that can't be read yet, like unfinished downloads, are tried again
+/// on the next scan.
+pub fn scan(
+    dir: PathBuf,
+    seen: HashSet<PathBuf>,
+    rules: &ScanRules,
+) -> (HashSet<PathBuf>, Vec<Song>) {
+    if !dir.is_dir() {
+        return (HashSet::new(), Vec::new());
+    }
+    let files = list_files(dir, rules);
+    let new: Vec<Song> = files
+        .iter()
+        .filter(|f| !seen.contains(*f))
+        .filter_map(|f| read_song_untagged(f.clone()))
+        .collect();
+    let mut read: HashSet<PathBuf> = files.into_iter().filter(|f| seen.contains(f)).collect();
+    read.extend(new.iter().map(|s| s.path.clone()));
+    (read, new)
+}
+
+/// write the tags on `song` to the file at `path`
+pub fn write_tags(song: &Song, path: &Path) -> lofty::error::Result<()> {
+    let mut tagged_file = read_from_path(path)?;
+    if tagged_file.primary_tag().is_none() {
+        tagged_file.insert_tag(Tag::new(tagged_file.primary_tag_type()));
+    }
+    let tag = tagged_file
+        .primary_tag_mut()
+        .expect("primary tag was just inserted");
+
+    let mut set_text = |key: ItemKey, value: &Option<String>| match value {
+        Some(value) => {
+            tag.insert_text(key, value.clone());
+        }
+        None => tag.remove_key(&key),
+    };
+    set_text(ItemKey::TrackTitle, &song.name);
+    set_text(ItemKey::TrackArtist, &song.track_artist);
+    set_text(ItemKey::AlbumArtist, &song.album_artist);
+    set_text(ItemKey::AlbumTitle, &song.album_name);
+    set_text(ItemKey::RecordingDate, &song.recording_date);
+    match song.track_number {
+        Some(n) => tag.set_track(n as u32),
+        None => tag.remove_track(),
+    }
+    match song.disc_number {
+        Some(n) => tag.set_disk(n as u32),
+        None => tag.remove_disk(),
+    }
+
+    tagged_file.save_to_path(path, WriteOptions::default())
+}
+
+/// move (or copy) the file into the library according to the configured template and
+/// write the edited tags. a copy gets the tags, the file in the downloads folder is left
+/// as it was. returns the song at its new location.
+pub fn accept(mut song: Song, config: &Config) -> Result<Song, String> {
+    let target = config
+        .library_dir
+        .join(template::render(&config.library_template, &song));
+    if target.exists() {
+        return Err(format!("{:?} already exists", target));
+    }
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("failed to create {:?}: {}", parent, e))?;
+    }
+    let tags_failed = |e| format!("failed to write tags: {}", e);
+    let import_failed = |e| format!("failed to import to {:?}: {}", target, e);
+    match config.import_mode {
+        ImportMode::Move => {
+            // the download is only changed once it's in the library, and goes back as it was
+            move_file(&song.path, &target).map_err(import_failed)?;
+            if let Err(e) = write_tags(&song, &target) {
+                return Err(match move_file(&target, &song.path) {
+                    Ok(()) => tags_failed(e),
+                    Err(back) => format!(
+                        "{}, and failed to move it back from {:?}: {}",
+                        tags_failed(e),
+                        target,
+                        back
+                    ),
+                });
+            }
+        }
+        ImportMode::Copy => {
+            fs::copy(&song.path, &target).map_err(import_failed)?;
+            if let Err(e) = write_tags(&song, &target) {
+                let _ = fs::remove_file(&target);
+                return Err(tags_failed(e));
+            }
+        }
+    }
+
+    song.path = target;
+    Ok(song)
+}
+
+/// rename `from` to `to`, falling back to copy and delete when they're on different devices
+pub fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    fs::rename(from, to).or_else(|_| {
+        if let Err(e) = fs::copy(from, to) {
+            // don't leave half a copy behind
+            let _ = fs::remove_file(to);
+            return Err(e);
+        }
+        fs::remove_file(from)
+    })
+}
@@ -0,0 +1,53 @@
+use std::{collections::HashMap, fs, path::PathBuf};
+
+use crate::{config::Config, Song};
+
+fn path() -> PathBuf {
+    Config::dir().join("library.json")
+}
+
+/// load the library database. the database keeps everything about a song that is too slow
+/// to work out again on every start, like analysis results.
+pub fn load() -> Vec<Song> {
+    let path = path();
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(_) => return Vec::new(),
+    };
+    serde_json::from_str(&contents).unwrap_or_else(|e| {
+        println!("error: failed to parse library database {:?}: {}", path, e);
+        Vec::new()
+    })
+}
+
+/// write the library database to disk
+pub fn save(songs: &[Song]) {
+    let path = path();
+    let contents = serde_json::to_string(songs).expect("failed to serialize library");
+    if let Err(e) = fs::create_dir_all(Config::dir()).and_then(|_| fs::write(&path, contents)) {
+        println!(
+            "error: failed to save library database to {:?}: {}",
+            path, e
+        );
+    }
+}
+
+/// combine a fresh scan with the database. tags always come from the scan, everything the
+/// tags can't tell us is carried over from the database entry with the same path.
+pub fn merge(scanned: Vec<Song>, cached: Vec<Song>) -> Vec<Song> {
+    let mut cached: HashMap<PathBuf, Song> =
+        cached.into_iter().map(|s| (s.path.clone(), s)).collect();
+    scanned
+        .into_iter()
+        .map(|mut song| {
+            if let Some(old) = cached.remove(&song.path) {
+                song.spectrum = old.spectrum;
+                song.loudness = old.loudness;
+                song.play_count = old.play_count;
+                // only there if it had to be scanned for, which is too slow to do every time
+                song.duration = song.duration.or(old.duration);
+            }
+            song
+        })
+        .collect()
+}
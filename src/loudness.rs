@@ -0,0 +1,330 @@
+use std::{
+    f64::consts::PI,
+    path::{Path, PathBuf},
+};
+
+use iced::futures::Stream;
+use serde::{Deserialize, Serialize};
+
+use crate::{analysis, decode::SampleReader, eq::Biquad, replaygain, replaygain::ReplayGain};
+
+/// loudness the ReplayGain 2.0 gains bring everything to
+pub const REFERENCE_LUFS: f32 = -18.0;
+/// the gating blocks are 400 ms long and start every 100 ms
+const SUB_BLOCKS_PER_BLOCK: usize = 4;
+const SUB_BLOCK_SECONDS: f64 = 0.1;
+/// blocks quieter than this are silence and don't count at all
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+/// blocks this far below the ungated loudness don't count either
+const RELATIVE_GATE_LU: f64 = 10.0;
+/// true peak is measured on the signal upsampled this many times
+const OVERSAMPLING: usize = 4;
+/// taps of the interpolation filter for each of the oversampled phases
+const TAPS_PER_PHASE: usize = 12;
+
+/// result of running the loudness job on a file, as in EBU R128
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Loudness {
+    /// integrated loudness of the track in LUFS
+    pub track_lufs: f32,
+    /// true peak of the track, linear with 1.0 being full scale
+    pub track_peak: f32,
+    /// the same for the whole album the track is on, `None` for tracks without an album
+    pub album_lufs: Option<f32>,
+    pub album_peak: Option<f32>,
+}
+
+impl Loudness {
+    /// the ReplayGain values that bring this track to [`REFERENCE_LUFS`]
+    pub fn replay_gain(&self) -> ReplayGain {
+        ReplayGain {
+            track_gain: Some(REFERENCE_LUFS - self.track_lufs),
+            track_peak: Some(self.track_peak),
+            album_gain: self.album_lufs.map(|lufs| REFERENCE_LUFS - lufs),
+            album_peak: self.album_peak,
+        }
+    }
+}
+
+/// what is measured for each track before the album numbers can be worked out
+struct Measurement {
+    /// mean square of every 400 ms gating block, after k-weighting. a track shorter than
+    /// that is a single block of all of it
+    blocks: Vec<f64>,
+    peak: f32,
+}
+
+/// the two k-weighting filters of BS.1770, worked out for any sample rate
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let rate = sample_rate as f64;
+
+    // high shelf modelling the acoustic effect of the head
+    let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad::new(
+        [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    // high pass taking out the lows the ear barely hears
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad::new(
+        [1.0, -2.0, 1.0],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+    [shelf, high_pass]
+}
+
+/// how much each channel counts, surround channels of 5.1 count more and lfe not at all
+fn channel_weight(channel: usize, channels: usize) -> f64 {
+    match (channels, channel) {
+        (6, 3) => 0.0,
+        (6, 4 | 5) => 1.41,
+        _ => 1.0,
+    }
+}
+
+/// windowed sinc low pass for upsampling, laid out so phase `p` uses every
+/// `OVERSAMPLING`th tap starting at `p`
+fn interpolation_filter() -> Vec<f64> {
+    let len = OVERSAMPLING * TAPS_PER_PHASE;
+    let center = (len - 1) as f64 / 2.0;
+    (0..len)
+        .map(|i| {
+            let x = (i as f64 - center) / OVERSAMPLING as f64;
+            let sinc = if x == 0.0 {
+                1.0
+            } else {
+                (PI * x).sin() / (PI * x)
+            };
+            let window = 0.5 - 0.5 * (2.0 * PI * i as f64 / (len - 1) as f64).cos();
+            sinc * window
+        })
+        .collect()
+}
+
+/// decode `path` and measure its gating blocks and true peak
+fn measure(path: &Path) -> Result<Measurement, String> {
+    let mut reader = SampleReader::open(path).map_err(|e| format!("failed to open: {}", e))?;
+    let sample_rate = reader.sample_rate();
+    let sub_block_frames = (sample_rate as f64 * SUB_BLOCK_SECONDS) as usize;
+    // high sample rates are already fine enough to catch the peaks between samples
+    let oversample = sample_rate < 176_400;
+    let filter = interpolation_filter();
+
+    let mut filters = Vec::new();
+    let mut history: Vec<Vec<f64>> = Vec::new();
+    let mut sub_block = Vec::new();
+    // weighted sum of squares and number of frames of every 100 ms
+    let mut sub_blocks: Vec<(f64, usize)> = Vec::new();
+    let mut frames = 0;
+    let mut peak = 0.0f64;
+
+    loop {
+        let channels = reader.channels();
+        let block = match reader.next_block() {
+            Ok(Some(block)) => block,
+            Ok(None) => break,
+            Err(e) => return Err(format!("failed to decode: {}", e)),
+        };
+        if filters.len() != channels {
+            filters = vec![k_weighting(sample_rate); channels];
+            history = vec![vec![0.0; TAPS_PER_PHASE]; channels];
+            sub_block = vec![0.0; channels];
+        }
+        for frame in block.chunks_exact(channels) {
+            for (channel, &sample) in frame.iter().enumerate() {
+                let sample = sample as f64;
+                let weighted = filters[channel]
+                    .iter_mut()
+                    .fold(sample, |x, filter| filter.process(x));
+                sub_block[channel] += weighted * weighted;
+
+                if oversample {
+                    let history = &mut history[channel];
+                    history.rotate_right(1);
+                    history[0] = sample;
+                    for phase in 0..OVERSAMPLING {
+                        let value: f64 = history
+                            .iter()
+                            .enumerate()
+                            .map(|(k, x)| filter[k * OVERSAMPLING + phase] * x)
+                            .sum();
+                        peak = peak.max(value.abs());
+                    }
+                } else {
+                    peak = peak.max(sample.abs());
+                }
+            }
+            frames += 1;
+            if frames == sub_block_frames {
+                sub_blocks.push((weighted_sum(&sub_block), frames));
+                sub_block.iter_mut().for_each(|s| *s = 0.0);
+                frames = 0;
+            }
+        }
+    }
+    // the end of the track rarely lines up with a sub-block, what is left over still counts
+    if frames > 0 {
+        sub_blocks.push((weighted_sum(&sub_block), frames));
+    }
+    if sub_blocks.is_empty() {
+        return Err("no audio in the file".to_string());
+    }
+
+    // a track shorter than one gating block is measured as a whole
+    let blocks = sub_blocks
+        .windows(SUB_BLOCKS_PER_BLOCK.min(sub_blocks.len()))
+        .map(|w| {
+            let (sum, frames) = w
+                .iter()
+                .fold((0.0, 0), |(sum, frames), (s, f)| (sum + s, frames + f));
+            sum / frames as f64
+        })
+        .collect();
+    Ok(Measurement {
+        blocks,
+        peak: peak as f32,
+    })
+}
+
+/// the sums of squares of the channels added up as much as each channel counts
+fn weighted_sum(sums: &[f64]) -> f64 {
+    sums.iter()
+        .enumerate()
+        .map(|(channel, sum)| channel_weight(channel, sums.len()) * sum)
+        .sum()
+}
+
+fn lufs(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+/// integrated loudness of the gating blocks, `None` if it's all silence
+fn integrated<'a>(blocks: impl Iterator<Item = &'a f64> + Clone) -> Option<f32> {
+    let mean = |threshold: f64| {
+        let (sum, count) = blocks
+            .clone()
+            .filter(|&&b| b > 0.0 && lufs(b) > threshold)
+            .fold((0.0, 0), |(sum, count), b| (sum + b, count + 1));
+        (count > 0).then(|| sum / count as f64)
+    };
+    let ungated = mean(ABSOLUTE_GATE_LUFS)?;
+    let relative = lufs(ungated) - RELATIVE_GATE_LU;
+    mean(relative.max(ABSOLUTE_GATE_LUFS)).map(|power| lufs(power) as f32)
+}
+
+/// measure an album (or a single track, as an album of none) and work out the loudness
+/// of each track and of the album as a whole. the results are in the order of `paths`.
+pub fn analyze_album(paths: &[PathBuf], is_album: bool) -> Vec<Result<Loudness, String>> {
+    let measurements: Vec<Result<Measurement, String>> =
+        paths.iter().map(|path| measure(path)).collect();
+    let measured = measurements.iter().filter_map(|m| m.as_ref().ok());
+    let album_lufs = integrated(measured.clone().flat_map(|m| m.blocks.iter()));
+    let album_peak = measured.map(|m| m.peak).reduce(f32::max);
+    let (album_lufs, album_peak) = match is_album {
+        true => (album_lufs, album_peak),
+        false => (None, None),
+    };
+    measurements
+        .into_iter()
+        .map(|measurement| {
+            let measurement = measurement?;
+            let track_lufs = integrated(measurement.blocks.iter())
+                .ok_or_else(|| "no audible signal".to_string())?;
+            Ok(Loudness {
+                track_lufs,
+                track_peak: measurement.peak,
+                album_lufs,
+                album_peak,
+            })
+        })
+        .collect()
+}
+
+/// analyze every group of files, each group being an album or a lone track. results are
+/// yielded per track once its group is done. with `write_tags` set the results are also
+/// written to the files as ReplayGain tags.
+pub fn analyze_all(
+    groups: Vec<(Vec<PathBuf>, bool)>,
+    write_tags: bool,
+) -> impl Stream<Item = (PathBuf, Result<Loudness, String>)> {
+    analysis::run_all(groups, move |paths, is_album| {
+        let results = analyze_album(paths, is_album);
+        paths
+            .iter()
+            .zip(results)
+            .map(|(path, result)| {
+                let loudness = result?;
+                if write_tags {
+                    replaygain::write_tags(path, &loudness.replay_gain())
+                        .map_err(|e| format!("failed to write tags: {}", e))?;
+                }
+                Ok(loudness)
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use hound::{SampleFormat, WavSpec, WavWriter};
+
+    use super::*;
+
+    /// a stereo wav of a 997 Hz sine at `amplitude`, the tone BS.1770 is calibrated with
+    fn sine_wav(name: &str, seconds: f64, amplitude: f32) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("thump-{}-{}.wav", name, std::process::id()));
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: 48000,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        };
+        let mut writer = WavWriter::create(&path, spec).expect("failed to create test wav");
+        for i in 0..(48000.0 * seconds) as usize {
+            let sample = amplitude * (2.0 * PI * 997.0 * i as f64 / 48000.0).sin() as f32;
+            for _ in 0..2 {
+                writer
+                    .write_sample(sample)
+                    .expect("failed to write test wav");
+            }
+        }
+        writer.finalize().expect("failed to finish test wav");
+        path
+    }
+
+    /// loudness of a single track
+    fn track_lufs(path: &Path) -> Result<f32, String> {
+        let result = analyze_album(&[path.to_path_buf()], false).remove(0);
+        std::fs::remove_file(path).ok();
+        result.map(|loudness| loudness.track_lufs)
+    }
+
+    #[test]
+    fn measures_a_sine() {
+        // a full scale sine in both channels is 0 LUFS, so this is -20
+        let lufs = track_lufs(&sine_wav("sine", 2.05, 0.1)).expect("not measured");
+        assert!((lufs + 20.0).abs() < 0.1, "{}", lufs);
+    }
+
+    #[test]
+    fn measures_tracks_shorter_than_a_block() {
+        let lufs = track_lufs(&sine_wav("short-sine", 0.25, 0.1)).expect("not measured");
+        assert!((lufs + 20.0).abs() < 0.1, "{}", lufs);
+    }
+
+    #[test]
+    fn empty_tracks_are_an_error() {
+        assert!(track_lufs(&sine_wav("empty", 0.0, 0.1)).is_err());
+    }
+}
@@ -1,44 +1,100 @@
 use std::{
-    collections::VecDeque,
-    fs::File,
-    io::BufReader,
+    collections::{HashMap, HashSet},
     path::PathBuf,
     process::exit,
-    sync::{
-        mpsc::{channel, Receiver, Sender},
-        Arc, Mutex,
-    },
+    sync::mpsc::{channel, Sender},
     time::Duration,
 };
 
+use albums::Album;
+use config::Config;
+use dsp::{DspCommand, EffectInfo, EffectSettings};
+use eq::{EqMode, EqPreset, GRAPHIC_BANDS, GRAPHIC_RANGE};
 use iced::{
-    border::top, futures::Stream, time, widget::{button, column, row, scrollable, svg, text}, Element, Subscription, Task
+    time,
+    widget::{
+        button, column, row, scrollable, slider, svg, text, text_input, vertical_slider, Space,
+    },
+    window, Element, Subscription, Task,
 };
-use play_manager::PlayerManager;
-use read_files::search_dir;
-use rhai::Engine;
-use rodio::{Decoder, OutputStream, Sink, Source};
+use inbox::TagField;
+use loudness::Loudness;
+use organize::PlannedMove;
+use output::OutputKind;
+use play_manager::{PlayerEvent, PlayerManager, PlayerMessage};
+use queue::{RepeatMode, ShuffleMode};
+use read_files::{search_dir, ScanRules};
+use replaygain::ReplayGain;
 use seeker::SeekPos;
+use serde::{Deserialize, Serialize};
+use session::{SavedQueue, Session};
+use spectrum::{SpectrumAnalysis, Verdict};
+use stretch::{MAX_PITCH, MAX_SPEED, MIN_SPEED};
 
+mod albums;
+mod analysis;
+mod config;
+mod decode;
+mod dsp;
+mod duration;
+mod eq;
+mod flac;
+mod inbox;
+mod library;
+mod loudness;
+mod organize;
+mod output;
 mod read_files;
+mod script;
 mod seeker;
+mod session;
 mod play_manager;
+mod playback;
+mod queue;
+mod replaygain;
+mod spectrum;
+mod stream;
+mod stretch;
+mod template;
 
 const NEXT_ICON: &[u8; 1714] = include_bytes!("../assets/next.svg");
 const PREV_ICON: &[u8; 1707] = include_bytes!("../assets/prev.svg");
 const PLAY_ICON: &[u8; 859] = include_bytes!("../assets/play.svg");
 const PAUSE_ICON: &[u8; 1624] = include_bytes!("../assets/pause.svg");
+/// port offered for streaming until another one is typed in
+const DEFAULT_STREAM_PORT: u16 = 8000;
+/// how often the session is saved, on top of when thump is closed
+const SESSION_SAVE: Duration = Duration::from_secs(30);
 
-#[derive(Debug, Clone)]
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Song {
     name: Option<String>,
     album_artist: Option<String>,
     track_artist: Option<String>,
     recording_date: Option<String>,
     track_number: Option<i32>,
+    /// number of tracks on this disc according to the tags
+    track_total: Option<i32>,
     disc_number: Option<i32>,
+    disc_total: Option<i32>,
     album_name: Option<String>,
     path: PathBuf,
+    /// result of the transcode detection job, `None` until the file has been analyzed
+    spectrum: Option<SpectrumAnalysis>,
+    #[serde(default)]
+    replay_gain: ReplayGain,
+    /// result of the loudness job, `None` until the file has been analyzed
+    loudness: Option<Loudness>,
+    /// rating from the tags, 0 to 1
+    #[serde(default)]
+    rating: Option<f32>,
+    /// how many times the song was started
+    #[serde(default)]
+    play_count: u32,
+    /// length of the audio, `None` until it has been worked out
+    #[serde(default)]
+    duration: Option<Duration>,
 }
 
 impl Song {
@@ -50,8 +106,39 @@ impl Song {
             track_artist: None,
             recording_date: None,
             track_number: None,
+            track_total: None,
             disc_number: None,
+            disc_total: None,
             album_name: None,
+            spectrum: None,
+            replay_gain: ReplayGain::default(),
+            loudness: None,
+            rating: None,
+            play_count: 0,
+            duration: None,
+        }
+    }
+
+    /// whether both songs are tagged as part of the same album
+    fn same_album(&self, other: &Song) -> bool {
+        self.album_name.is_some()
+            && self.album_name == other.album_name
+            && self.album_artist == other.album_artist
+    }
+
+    /// whether this is the track of the album right after `other`, the first track of the
+    /// next disc counting as right after the last one
+    fn follows(&self, other: &Song) -> bool {
+        let disc = |s: &Song| s.disc_number.unwrap_or(1);
+        if !self.same_album(other) {
+            return false;
+        }
+        match (other.track_number, self.track_number) {
+            (Some(before), Some(after)) if disc(self) == disc(other) => after == before + 1,
+            (Some(before), Some(1)) if disc(self) == disc(other) + 1 => {
+                other.track_total.is_none_or(|total| total == before)
+            }
+            _ => false,
         }
     }
 }
@@ -60,78 +147,176 @@ impl Song {
 async fn main() {
     iced::application("Thump", State::update, State::view)
         .subscription(State::subscription)
+        // the session is saved before the window goes
+        .exit_on_close_request(false)
         .run_with(State::new)
         .unwrap();
     exit(1)
 }
 
-enum PlayerMessage {
-    Play,
-    Paus,
-    Stop,
-    Next,
-    Prev,
-    Seek(SeekPos),
-    GetPos(Box<dyn FnMut(SeekPos) + Send>),
-    PlaySong(Song),
-}
-unsafe impl Send for PlayerMessage { }
-
 #[derive(Debug, Clone)]
 enum Message {
     Play,
     Pause,
     Next,
     Prev,
-    SeekUpdate,
     SeekChanged(SeekPos),
     Seeking,
     DoneSeeking,
     SongSelected(Song),
+    QueueJump(usize),
+    Player(PlayerEvent),
+    SaveSession,
+    CloseRequested(window::Id),
+    ViewSelected(View),
+    AnalyzeSpectrum,
+    SpectrumAnalyzed(PathBuf, Result<SpectrumAnalysis, String>),
+    AnalyzeLoudness,
+    LoudnessAnalyzed(PathBuf, Result<Loudness, String>),
+    /// count the packets of the songs whose length the tags and headers don't give
+    ScanDurations,
+    DurationScanned(PathBuf, Result<Duration, String>),
+    ScanInbox,
+    InboxScanned(HashSet<PathBuf>, Vec<Song>),
+    InboxSelected(PathBuf),
+    InboxEdited(TagField, String),
+    InboxAccept,
+    InboxAccepted(PathBuf, Result<Song, String>),
+    EqPresetSelected(String),
+    EqBypassToggled,
+    /// a band of the graphic equalizer was moved
+    EqGainChanged(usize, f32),
+    EqImportPathChanged(String),
+    EqImport,
+    SaveConfig,
+    Dsp(DspCommand),
+    VolumeChanged(f32),
+    MuteToggled,
+    SpeedChanged(f32),
+    /// move on to the next shuffle mode
+    ShuffleToggled,
+    /// move on to the next repeat mode
+    RepeatToggled,
+    ListOutputDevices,
+    OutputDevicesListed(Vec<String>),
+    /// play on this device, `None` for the default one
+    OutputDeviceSelected(Option<String>),
+    /// play on an output that isn't a sound card
+    OutputSelected(OutputKind),
+    OutputWavPathChanged(String),
+    OutputPipePathChanged(String),
+    StreamPortChanged(String),
+    /// start streaming on the port typed in, or stop
+    StreamToggled,
+    PitchChanged(f32),
+    OrganizePreview,
+    OrganizePlanned(Vec<PlannedMove>),
+    OrganizeApply,
+    OrganizeApplied(Vec<PlannedMove>, Vec<String>),
+}
+
+/// the page shown below the player controls
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum View {
+    #[default]
+    Library,
+    Suspicious,
+    Loudness,
+    Inbox,
+    Organize,
+    Equalizer,
+    Effects,
+    Albums,
+    Incomplete,
+    Queue,
+    Settings,
 }
 
 #[derive(Debug)]
 struct State {
-    player_tx: Sender<PlayerMessage>,
     tx_rust: Sender<String>,
     player_manager: PlayerManager,
     playing: bool,
     seek_value: SeekPos,
     seeking: bool,
     songs: Vec<Song>,
+    /// `songs` grouped into albums, redone whenever songs are added
+    albums: Vec<Album>,
     now_playing: Option<Song>,
-    player_que: VecDeque<Song>,
+    /// how far into `now_playing` playback is
+    position: Duration,
+    /// length of the song that is playing, `None` while it isn't known and it can't be seeked
+    duration: Option<Duration>,
+    /// copy of the engine's queue, for showing it
+    queue: Vec<Song>,
+    queue_index: Option<usize>,
+    /// indices into `queue` in the order they are played
+    queue_order: Vec<usize>,
+    /// the last error the player reported
+    player_error: Option<String>,
+    view: View,
+    /// number of files the spectrum analysis job still has to get through
+    analysis_pending: usize,
+    /// number of files the loudness job still has to get through
+    loudness_pending: usize,
+    /// number of files the duration scan still has to get through
+    durations_pending: usize,
+    /// play counts went up since the library was saved. they are saved with the session
+    /// instead of on every track.
+    library_dirty: bool,
+    config: Config,
+    /// new downloads waiting to be reviewed and accepted into the library
+    inbox: Vec<Song>,
+    /// files in the downloads folder that were read already, so only new ones get parsed
+    inbox_seen: HashSet<PathBuf>,
+    inbox_scanning: bool,
+    inbox_selected: Option<PathBuf>,
+    /// the last thing that went wrong in the inbox
+    inbox_error: Option<String>,
+    /// dry run of the reorganize command, waiting to be applied
+    organize_plan: Option<Vec<PlannedMove>>,
+    organize_errors: Vec<String>,
+    /// path typed into the AutoEQ import box
+    eq_import_path: String,
+    eq_error: Option<String>,
+    /// copy of the engine's effect chain
+    effects: Vec<EffectInfo>,
+    /// devices that can be played on, as of the last time the settings were opened
+    output_devices: Vec<String>,
+    /// the device the engine is playing on
+    output_device: Option<String>,
+    /// path typed into the WAV output box
+    output_wav_path: String,
+    /// path typed into the pipe output box
+    output_pipe_path: String,
+    /// port typed into the stream box
+    stream_port: String,
+    /// the port the engine is streaming on
+    streaming: Option<u16>,
 }
 
 impl State {
     fn new() -> (State, Task<Message>) {
-        let (tx, rx) = channel::<PlayerMessage>();
+        let config = Config::load();
+        let player_manager = PlayerManager::new(&config);
         let (tx_rust, rx_rhai) = channel();
-        let (tx_rhai, rx_rust) = channel();
-        tokio::spawn(async move {
-            let mut engine = Engine::new();
-            engine
-                .register_fn("get", move || rx_rhai.recv().unwrap_or_default())
-                .register_fn("put", move |v: String| tx_rhai.send(v).unwrap());
-
-            engine
-                .run(
-                    r#"
-        print("from script");
-        loop {
-            let value = get();
-            // print(`got ${value}`);
-        }
-        "#,
-                )
-                .expect("failed to run script");
-        });
-
-        // tokio::spawn(play_manager(rx, tx_rust));
+        let (tx_rhai, _rx_rust) = channel();
+        script::spawn(rx_rhai, tx_rhai, player_manager.sender(), &config);
 
         let seek_value = SeekPos::from_range(0.0, 1.0);
+        let session = session::load();
+        if !session.queue.entries.is_empty() {
+            player_manager.send(PlayerMessage::Restore(Box::new(session.queue.clone())));
+        }
+        let stream_port = config.stream_port.unwrap_or(DEFAULT_STREAM_PORT).to_string();
 
-        let songs = search_dir("/Users/jonas/Soulseek Downloads/complete");
+        let mut songs = search_dir(&config.library_dir, &ScanRules::new(&config));
+        if let Some(downloads) = &config.downloads_dir {
+            songs.retain(|s| !s.path.starts_with(downloads));
+        }
+        let songs = library::merge(songs, library::load());
+        library::save(&songs);
         songs.iter().for_each(|s| {
             println!("song: {:?} {:?}", s.name, s.track_artist);
         });
@@ -140,45 +325,74 @@ impl State {
 
         (
             State {
-                player_tx: tx,
                 tx_rust,
-                player_manager: PlayerManager::new(),
+                player_manager,
                 playing: false,
                 seek_value,
                 seeking: false,
+                albums: albums::albums(&songs),
                 songs,
                 now_playing: None,
-                player_que: VecDeque::new(),
+                // the engine sends all of this once it restored the queue, until then it's
+                // what gets saved again
+                position: session.queue.position,
+                duration: None,
+                queue: session.queue.entries,
+                queue_index: session.queue.current,
+                queue_order: session.queue.order,
+                player_error: None,
+                view: session.view,
+                analysis_pending: 0,
+                loudness_pending: 0,
+                durations_pending: 0,
+                library_dirty: false,
+                config,
+                inbox: Vec::new(),
+                inbox_seen: HashSet::new(),
+                inbox_scanning: false,
+                inbox_selected: None,
+                inbox_error: None,
+                organize_plan: None,
+                organize_errors: Vec::new(),
+                eq_import_path: String::new(),
+                eq_error: None,
+                effects: Vec::new(),
+                output_devices: Vec::new(),
+                output_device: None,
+                output_wav_path: String::new(),
+                output_pipe_path: String::new(),
+                stream_port,
+                streaming: None,
             },
-            Task::none()
-            // Task::future(play_manager(rx, tx_rust)),
+            Task::batch([
+                Task::done(Message::ScanInbox),
+                Task::done(Message::ScanDurations),
+                Task::done(Message::ViewSelected(session.view)),
+            ]),
         )
     }
 
     fn update(&mut self, message: Message) -> Task<Message> {
         match message {
             Message::Play => {
-                println!("playing msg received. empty: {}", self.player_manager.sink.empty());
-                self.player_manager.sink.play();
+                println!("playing msg received");
+                self.player_manager.send(PlayerMessage::Play);
                 self.tx_rust
                     .send("play".to_string())
                     .expect("failed to send message to rhai");
-                self.playing = true;
                 Task::none()
             }
             Message::Pause => {
                 println!("paused");
-                self.player_manager.sink.pause();
+                self.player_manager.send(PlayerMessage::Paus);
                 self.tx_rust
                     .send("paus".to_string())
                     .expect("failed to send message to rhai");
-                self.playing = false;
                 Task::none()
             }
             Message::Next => {
                 println!("next");
-                // println!("not yet working");
-                self.player_manager.sink.skip_one();
+                self.player_manager.send(PlayerMessage::Next);
                 self.tx_rust
                     .send("next".to_string())
                     .expect("failed to send message to rhai");
@@ -186,7 +400,7 @@ impl State {
             }
             Message::Prev => {
                 println!("prev");
-                println!("not yet working");
+                self.player_manager.send(PlayerMessage::Prev);
                 self.tx_rust
                     .send("prev".to_string())
                     .expect("failed to send message to rhai");
@@ -194,25 +408,13 @@ impl State {
             }
             Message::SeekChanged(val) => {
                 println!("seeking {:?}", val);
-                let pos = val.get() * self.player_manager.duration.as_secs_f64();
-                self.player_manager.sink.try_seek(Duration::from_secs_f64(pos))
-                    .expect("could not seek");
+                self.player_manager.send(PlayerMessage::Seek(val));
                 self.tx_rust
                     .send("seek".to_string())
                     .expect("failed to send message to rhai");
                 self.seek_value = val;
                 Task::none()
             }
-            Message::SeekUpdate => {
-
-                let pos = self.player_manager.sink.get_pos();
-                // println!("getpos {:?}", pos);
-                self.seek_value = SeekPos::from_secs_percent(pos.as_secs_f64(), self.player_manager.duration);
-                if self.seek_value.get() >= 0.999999 {
-                    println!("next_song");
-                }
-                Task::none()
-            }
             Message::Seeking => {
                 self.seeking = true;
                 Task::none()
@@ -222,43 +424,588 @@ impl State {
                 Task::none()
             }
             Message::SongSelected(song) => {
-                let file =
-                BufReader::new(File::open(song.path.clone()).expect("failed to load test file"));
-                let source =
-                Decoder::new(file).expect("failed to create decoder from test file");
-                // self.duration = source
-                //     .total_duration()
-                //     .expect("failed to get souce duration");
-                let duration = source.total_duration().expect("failed to get source duration");
-                self.player_manager.sink.append(source);
-                // Message::SetDuration(duration)
-                // return Subscription::none().map(move |_: ()| Message::SetDuration(duration));
-                self.player_manager.duration = duration;
-                self.player_que.push_back(song);
-                Task::done(Message::Play)
+                self.player_manager.send(PlayerMessage::Enqueue(Box::new(song)));
+                Task::none()
+            }
+            Message::QueueJump(index) => {
+                self.player_manager.send(PlayerMessage::Jump(index));
+                Task::none()
+            }
+            Message::Player(event) => {
+                match event {
+                    PlayerEvent::TrackStarted(song, duration) => {
+                        if let Some(played) = self.songs.iter_mut().find(|s| s.path == song.path) {
+                            played.play_count += 1;
+                            self.library_dirty = true;
+                        }
+                        self.now_playing = Some(*song);
+                        self.duration = duration;
+                        self.player_error = None;
+                    }
+                    PlayerEvent::TrackLoaded(song, duration) => {
+                        self.now_playing = Some(*song);
+                        self.duration = duration;
+                    }
+                    PlayerEvent::TrackEnded => self.now_playing = None,
+                    PlayerEvent::DurationChanged(duration) => self.duration = Some(duration),
+                    PlayerEvent::PositionChanged(pos) => {
+                        self.position = pos;
+                        if let Some(duration) = self.duration.filter(|_| !self.seeking) {
+                            self.seek_value =
+                                SeekPos::from_secs_percent(pos.as_secs_f64(), duration);
+                        }
+                    }
+                    PlayerEvent::PlayingChanged(playing) => self.playing = playing,
+                    PlayerEvent::QueueChanged(queue, index, order) => {
+                        self.queue = queue;
+                        self.queue_index = index;
+                        self.queue_order = order;
+                    }
+                    PlayerEvent::DspChanged(effects) => {
+                        let settings: Vec<_> = effects.iter().map(|e| e.settings()).collect();
+                        // parameters move with every step of a slider, they are saved once
+                        // it's let go. effects added, removed or moved are saved right away.
+                        let names = |chain: &[EffectSettings]| {
+                            chain.iter().map(|e| e.name.clone()).collect::<Vec<_>>()
+                        };
+                        let rearranged = names(&settings) != names(&self.config.dsp_chain);
+                        self.config.dsp_chain = settings;
+                        if rearranged {
+                            self.config.save();
+                        }
+                        self.effects = effects;
+                    }
+                    PlayerEvent::ShuffleChanged(mode) => {
+                        if mode != self.config.shuffle {
+                            self.config.shuffle = mode;
+                            self.config.save();
+                        }
+                    }
+                    PlayerEvent::RepeatChanged(mode) => {
+                        if mode != self.config.repeat {
+                            self.config.repeat = mode;
+                            self.config.save();
+                        }
+                    }
+                    PlayerEvent::OutputChanged(device) => {
+                        self.output_device = device;
+                    }
+                    PlayerEvent::StreamChanged(port) => {
+                        self.streaming = port;
+                    }
+                    PlayerEvent::Error(e) => {
+                        println!("error: {}", e);
+                        self.player_error = Some(e);
+                    }
+                }
+                Task::none()
+            }
+            Message::SaveSession => {
+                self.save_session();
+                Task::none()
+            }
+            Message::CloseRequested(id) => {
+                self.save_session();
+                window::close(id)
+            }
+            Message::ViewSelected(view) => {
+                self.view = view;
+                if view == View::Settings {
+                    return Task::done(Message::ListOutputDevices);
+                }
+                Task::none()
+            }
+            Message::AnalyzeSpectrum => {
+                if self.analysis_pending > 0 {
+                    return Task::none();
+                }
+                let paths: Vec<PathBuf> = self
+                    .songs
+                    .iter()
+                    .filter(|s| s.spectrum.is_none() && spectrum::is_lossless_container(&s.path))
+                    .map(|s| s.path.clone())
+                    .collect();
+                self.analysis_pending = paths.len();
+                Task::run(spectrum::analyze_all(paths), |(path, result)| {
+                    Message::SpectrumAnalyzed(path, result)
+                })
+            }
+            Message::SpectrumAnalyzed(path, result) => {
+                self.analysis_pending = self.analysis_pending.saturating_sub(1);
+                match result {
+                    Ok(analysis) => {
+                        if let Some(song) = self.songs.iter_mut().find(|s| s.path == path) {
+                            song.spectrum = Some(analysis);
+                        }
+                    }
+                    Err(e) => println!("error: spectrum analysis failed for {:?}: {}", path, e),
+                }
+                if self.analysis_pending == 0 {
+                    library::save(&self.songs);
+                }
+                Task::none()
+            }
+            Message::AnalyzeLoudness => {
+                if self.loudness_pending > 0 {
+                    return Task::none();
+                }
+                // whole albums are analyzed again when any of their tracks is new, the
+                // album loudness needs all of them
+                let mut groups: Vec<(Vec<PathBuf>, bool)> = albums::group(&self.songs)
+                    .into_values()
+                    .filter(|songs| songs.iter().any(|s| s.loudness.is_none()))
+                    .map(|songs| (songs.iter().map(|s| s.path.clone()).collect(), true))
+                    .collect();
+                groups.extend(
+                    self.songs
+                        .iter()
+                        .filter(|s| s.album_name.is_none() && s.loudness.is_none())
+                        .map(|s| (vec![s.path.clone()], false)),
+                );
+                self.loudness_pending = groups.iter().map(|(paths, _)| paths.len()).sum();
+                Task::run(
+                    loudness::analyze_all(groups, self.config.write_replay_gain_tags),
+                    |(path, result)| Message::LoudnessAnalyzed(path, result),
+                )
+            }
+            Message::LoudnessAnalyzed(path, result) => {
+                self.loudness_pending = self.loudness_pending.saturating_sub(1);
+                match result {
+                    Ok(loudness) => {
+                        if let Some(song) = self.songs.iter_mut().find(|s| s.path == path) {
+                            song.loudness = Some(loudness);
+                            if self.config.write_replay_gain_tags {
+                                song.replay_gain = loudness.replay_gain();
+                            }
+                        }
+                    }
+                    Err(e) => println!("error: loudness analysis failed for {:?}: {}", path, e),
+                }
+                if self.loudness_pending == 0 {
+                    library::save(&self.songs);
+                }
+                Task::none()
+            }
+            Message::ScanDurations => {
+                if self.durations_pending > 0 {
+                    return Task::none();
+                }
+                let paths: Vec<PathBuf> = self
+                    .songs
+                    .iter()
+                    .filter(|s| s.duration.is_none())
+                    .map(|s| s.path.clone())
+                    .collect();
+                self.durations_pending = paths.len();
+                Task::run(duration::scan_all(paths), |(path, result)| {
+                    Message::DurationScanned(path, result)
+                })
+            }
+            Message::DurationScanned(path, result) => {
+                self.durations_pending = self.durations_pending.saturating_sub(1);
+                match result {
+                    Ok(duration) => {
+                        if let Some(song) = self.songs.iter_mut().find(|s| s.path == path) {
+                            song.duration = Some(duration);
+                        }
+                        // queued before it was known, the decoder's guess is in use there
+                        self.player_manager.send(PlayerMessage::SetDuration(path, duration));
+                    }
+                    Err(e) => println!("error: could not work out the length of {:?}: {}", path, e),
+                }
+                if self.durations_pending == 0 {
+                    library::save(&self.songs);
+                }
+                Task::none()
+            }
+            Message::ScanInbox => {
+                let Some(dir) = self.config.downloads_dir.clone() else {
+                    return Task::none();
+                };
+                if self.inbox_scanning {
+                    return Task::none();
+                }
+                self.inbox_scanning = true;
+                let seen = self.inbox_seen.clone();
+                let rules = ScanRules::new(&self.config);
+                Task::perform(
+                    tokio::task::spawn_blocking(move || inbox::scan(dir, seen, &rules)),
+                    |result| {
+                        let (files, new) = result.expect("inbox scan panicked");
+                        Message::InboxScanned(files, new)
+                    },
+                )
+            }
+            Message::InboxScanned(files, new) => {
+                self.inbox_scanning = false;
+                self.inbox.retain(|s| files.contains(&s.path));
+                self.inbox.extend(new);
+                self.inbox.sort_by(|a, b| a.path.cmp(&b.path));
+                self.inbox_seen = files;
+                Task::none()
+            }
+            Message::InboxSelected(path) => {
+                self.inbox_selected = Some(path);
+                self.inbox_error = None;
+                Task::none()
+            }
+            Message::InboxEdited(field, value) => {
+                if let Some(song) = self.selected_inbox_song_mut() {
+                    field.set(song, value);
+                }
+                Task::none()
+            }
+            Message::InboxAccept => {
+                let Some(song) = self.selected_inbox_song_mut().cloned() else {
+                    return Task::none();
+                };
+                let config = self.config.clone();
+                let path = song.path.clone();
+                Task::perform(
+                    tokio::task::spawn_blocking(move || inbox::accept(song, &config)),
+                    move |result| {
+                        let result = result.unwrap_or_else(|e| Err(format!("import panicked: {}", e)));
+                        Message::InboxAccepted(path.clone(), result)
+                    },
+                )
+            }
+            Message::InboxAccepted(path, result) => {
+                match result {
+                    Ok(song) => {
+                        self.inbox.retain(|s| s.path != path);
+                        self.inbox_selected = None;
+                        self.inbox_error = None;
+                        self.songs.push(song);
+                        self.albums = albums::albums(&self.songs);
+                        library::save(&self.songs);
+                    }
+                    Err(e) => {
+                        println!("error: failed to accept {:?}: {}", path, e);
+                        self.inbox_error = Some(e);
+                    }
+                }
+                Task::none()
+            }
+            Message::EqPresetSelected(name) => {
+                self.config.eq_preset = Some(name);
+                self.config.eq_bypass = false;
+                self.eq_changed();
+                self.config.save();
+                Task::none()
+            }
+            Message::EqBypassToggled => {
+                self.config.eq_bypass = !self.config.eq_bypass;
+                self.eq_changed();
+                self.config.save();
+                Task::none()
+            }
+            Message::EqGainChanged(band, gain) => {
+                let Some(mut preset) = self.selected_eq_preset() else {
+                    return Task::none();
+                };
+                let EqMode::Graphic { gains } = &mut preset.mode else {
+                    return Task::none();
+                };
+                gains[band] = gain;
+                preset.preamp = eq::graphic_preamp(gains);
+                // built in presets stay as they are, changes go into a new preset of our own
+                if eq::builtin_presets().iter().any(|p| p.name == preset.name)
+                    && !self.config.eq_presets.iter().any(|p| p.name == preset.name)
+                {
+                    let taken = self.config.all_eq_presets();
+                    preset.name = (1..)
+                        .map(|n| match n {
+                            1 => "custom".to_string(),
+                            n => format!("custom {}", n),
+                        })
+                        .find(|name| !taken.iter().any(|p| &p.name == name))
+                        .expect("ran out of numbers");
+                }
+                self.config.eq_preset = Some(preset.name.clone());
+                self.config.eq_presets.retain(|p| p.name != preset.name);
+                self.config.eq_presets.push(preset);
+                self.eq_changed();
+                Task::none()
+            }
+            Message::EqImportPathChanged(path) => {
+                self.eq_import_path = path;
+                Task::none()
+            }
+            Message::EqImport => {
+                match eq::import_autoeq(&PathBuf::from(self.eq_import_path.trim())) {
+                    Ok(preset) => {
+                        self.config.eq_preset = Some(preset.name.clone());
+                        self.config.eq_presets.retain(|p| p.name != preset.name);
+                        self.config.eq_presets.push(preset);
+                        self.eq_import_path.clear();
+                        self.eq_error = None;
+                        self.eq_changed();
+                        self.config.save();
+                    }
+                    Err(e) => {
+                        println!("error: failed to import {:?}: {}", self.eq_import_path, e);
+                        self.eq_error = Some(e);
+                    }
+                }
+                Task::none()
+            }
+            Message::SaveConfig => {
+                self.config.save();
+                Task::none()
+            }
+            Message::VolumeChanged(volume) => {
+                self.config.volume = volume;
+                // unmuting by moving the slider is what people expect
+                if self.config.muted {
+                    self.config.muted = false;
+                    self.player_manager.send(PlayerMessage::SetMuted(false));
+                }
+                self.player_manager.send(PlayerMessage::SetVolume(volume));
+                Task::none()
+            }
+            Message::MuteToggled => {
+                self.config.muted = !self.config.muted;
+                self.player_manager.send(PlayerMessage::SetMuted(self.config.muted));
+                self.config.save();
+                Task::none()
+            }
+            Message::SpeedChanged(speed) => {
+                self.config.speed = speed;
+                self.player_manager.send(PlayerMessage::SetSpeed(speed));
+                Task::none()
+            }
+            Message::ShuffleToggled => {
+                self.config.shuffle = match self.config.shuffle {
+                    ShuffleMode::Off => ShuffleMode::Track,
+                    ShuffleMode::Track => ShuffleMode::Album,
+                    ShuffleMode::Album => ShuffleMode::Weighted,
+                    ShuffleMode::Weighted => ShuffleMode::Off,
+                };
+                self.player_manager.send(PlayerMessage::SetShuffle(self.config.shuffle));
+                self.config.save();
+                Task::none()
+            }
+            Message::RepeatToggled => {
+                self.config.repeat = match self.config.repeat {
+                    RepeatMode::Off => RepeatMode::All,
+                    RepeatMode::All => RepeatMode::Album,
+                    RepeatMode::Album => RepeatMode::One,
+                    RepeatMode::One => RepeatMode::Off,
+                };
+                self.player_manager.send(PlayerMessage::SetRepeat(self.config.repeat));
+                self.config.save();
+                Task::none()
+            }
+            Message::ListOutputDevices => Task::perform(
+                tokio::task::spawn_blocking(output::output_devices),
+                |devices| Message::OutputDevicesListed(devices.unwrap_or_default()),
+            ),
+            Message::OutputDevicesListed(devices) => {
+                self.output_devices = devices;
+                Task::none()
+            }
+            Message::OutputDeviceSelected(device) => {
+                self.config.output = OutputKind::Device;
+                self.config.output_device = device.clone();
+                self.player_manager.send(PlayerMessage::SetOutputDevice(device));
+                self.config.save();
+                Task::none()
+            }
+            Message::OutputSelected(kind) => {
+                self.config.output = kind.clone();
+                self.player_manager.send(PlayerMessage::SetOutput(kind));
+                self.config.save();
+                Task::none()
+            }
+            Message::OutputWavPathChanged(path) => {
+                self.output_wav_path = path;
+                Task::none()
+            }
+            Message::OutputPipePathChanged(path) => {
+                self.output_pipe_path = path;
+                Task::none()
+            }
+            Message::StreamPortChanged(port) => {
+                self.stream_port = port;
+                Task::none()
+            }
+            Message::StreamToggled => {
+                let port = match self.streaming {
+                    Some(_) => None,
+                    None => match self.stream_port.trim().parse() {
+                        Ok(port) => Some(port),
+                        Err(_) => {
+                            println!("error: {:?} is not a port", self.stream_port);
+                            return Task::none();
+                        }
+                    },
+                };
+                self.config.stream_port = port;
+                self.player_manager.send(PlayerMessage::SetStream(port));
+                self.config.save();
+                Task::none()
+            }
+            Message::PitchChanged(pitch) => {
+                self.config.pitch_semitones = pitch;
+                self.player_manager.send(PlayerMessage::SetPitch(pitch));
+                Task::none()
+            }
+            Message::Dsp(command) => {
+                self.player_manager.send(PlayerMessage::Dsp(command));
+                Task::none()
+            }
+            Message::OrganizePreview => {
+                let songs = self.songs.clone();
+                let config = self.config.clone();
+                Task::perform(
+                    tokio::task::spawn_blocking(move || organize::plan(&songs, &config)),
+                    |plan| Message::OrganizePlanned(plan.expect("organize plan panicked")),
+                )
+            }
+            Message::OrganizePlanned(plan) => {
+                self.organize_plan = Some(plan);
+                self.organize_errors.clear();
+                Task::none()
+            }
+            Message::OrganizeApply => {
+                let Some(plan) = self.organize_plan.take() else {
+                    return Task::none();
+                };
+                let root = self.config.library_dir.clone();
+                Task::perform(
+                    tokio::task::spawn_blocking(move || organize::apply(plan, &root)),
+                    |result| {
+                        let (done, errors) = result.expect("organize panicked");
+                        Message::OrganizeApplied(done, errors)
+                    },
+                )
+            }
+            Message::OrganizeApplied(done, errors) => {
+                let moved: HashMap<PathBuf, PathBuf> = done
+                    .into_iter()
+                    .filter(|m| m.audio)
+                    .map(|m| (m.from, m.to))
+                    .collect();
+                let songs = self.songs.iter_mut().chain(&mut self.queue);
+                for song in songs.chain(&mut self.now_playing) {
+                    if let Some(to) = moved.get(&song.path) {
+                        song.path = to.clone();
+                    }
+                }
+                self.albums = albums::albums(&self.songs);
+                library::save(&self.songs);
+                // the queue the session is saved from follows right away, the engine's own
+                // copy once it got the message
+                self.save_session();
+                self.player_manager.send(PlayerMessage::FilesMoved(moved));
+                errors.iter().for_each(|e| println!("error: {}", e));
+                self.organize_errors = errors;
+                Task::none()
             }
         }
     }
     fn view(&self) -> Element<Message> {
         column![
-            play_controls(self.playing),
+            play_controls(self.playing, &self.config),
             // seek_bar(*self.seek_value.lock().expect("mutex failed to lock")),
-            seeker::seeker(
-                self.seek_value,
-                self.player_tx.clone(),
-            ),
-            now_playing(),
-            song_browser(&self.songs)
+            // nothing to seek in without a length, the space keeps the rest where it is
+            match self.duration {
+                Some(_) => seeker::seeker(self.seek_value, self.player_manager.sender()).into(),
+                None => Element::from(Space::new(200, 12)),
+            },
+            now_playing(self.now_playing.as_ref(), self.player_error.as_deref()),
+            view_tabs(self.view),
+            match self.view {
+                View::Library => song_browser(&self.songs),
+                View::Suspicious => suspicious_files(&self.songs, self.analysis_pending),
+                View::Loudness => loudness_view(&self.songs, self.loudness_pending),
+                View::Inbox => inbox_view(
+                    &self.inbox,
+                    self.inbox_selected.as_ref(),
+                    self.inbox_error.as_deref(),
+                    &self.config
+                ),
+                View::Organize => organize_view(
+                    self.organize_plan.as_deref(),
+                    &self.organize_errors,
+                    &self.config
+                ),
+                View::Equalizer => equalizer_view(
+                    &self.config,
+                    self.selected_eq_preset(),
+                    &self.eq_import_path,
+                    self.eq_error.as_deref()
+                ),
+                View::Effects => effects_view(&self.effects),
+                View::Albums => album_browser(&self.albums),
+                View::Incomplete => incomplete_albums(&self.albums),
+                View::Queue => queue_view(&self.queue, self.queue_index, &self.queue_order),
+                View::Settings => settings_view(
+                    &self.config,
+                    &self.output_devices,
+                    self.output_device.as_deref(),
+                    &self.output_wav_path,
+                    &self.output_pipe_path,
+                    &self.stream_port,
+                    self.streaming,
+                ),
+            }
         ]
         .into()
     }
     fn subscription(&self) -> Subscription<Message> {
-        let seeking = if !self.seeking {
-            time::every(Duration::from_millis(100)).map(|_| Message::SeekUpdate)
+        let inbox = if self.config.downloads_dir.is_some() {
+            time::every(Duration::from_secs(5)).map(|_| Message::ScanInbox)
         } else {
             Subscription::none()
         };
-        Subscription::batch([seeking /* self.player_manager.player_subscription() */])
+        Subscription::batch([
+            inbox,
+            self.player_manager.player_subscription().map(Message::Player),
+            time::every(SESSION_SAVE).map(|_| Message::SaveSession),
+            window::close_requests().map(Message::CloseRequested),
+        ])
+    }
+
+    /// what to pick up from on the next start
+    fn session(&self) -> Session {
+        Session {
+            queue: SavedQueue {
+                entries: self.queue.clone(),
+                current: self.queue_index,
+                order: self.queue_order.clone(),
+                position: self.position,
+            },
+            view: self.view,
+        }
+    }
+
+    /// save the session, and the library if play counts changed
+    fn save_session(&mut self) {
+        session::save(&self.session());
+        if self.library_dirty {
+            library::save(&self.songs);
+            self.library_dirty = false;
+        }
+    }
+
+    /// hand the equalizer settings to the engine
+    fn eq_changed(&self) {
+        self.player_manager
+            .send(PlayerMessage::SetEqualizer(self.config.equalizer()));
+    }
+
+    fn selected_eq_preset(&self) -> Option<EqPreset> {
+        let name = self.config.eq_preset.as_ref()?;
+        self.config
+            .all_eq_presets()
+            .into_iter()
+            .find(|p| &p.name == name)
+    }
+
+    fn selected_inbox_song_mut(&mut self) -> Option<&mut Song> {
+        let selected = self.inbox_selected.as_ref()?;
+        self.inbox.iter_mut().find(|s| &s.path == selected)
     }
 }
 
@@ -276,18 +1023,445 @@ fn song_browser(songs: &Vec<Song>) -> Element<'static, Message> {
 
 fn song(song: Song, name_width: f32, artist_width: f32) -> Element<'static, Message> {
     button(row![
-        text(song.name.as_ref().expect("no song name").clone()).width(name_width),
-        text(song.track_artist.as_ref().expect("no song name").clone()).width(artist_width),
+        text(song.name.clone().unwrap_or_default()).width(name_width),
+        text(song.track_artist.clone().unwrap_or_default()).width(artist_width),
+        text(song.duration.map(format_duration).unwrap_or_default()),
     ])
     .on_press_with(move || Message::SongSelected(song.clone()))
     .into()
 }
 
-fn now_playing() -> Element<'static, Message> {
-    text("now playing").into()
+/// like 3:07, or 1:02:45 for the long ones
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs / 3600 {
+        0 => format!("{}:{:02}", secs / 60, secs % 60),
+        hours => format!("{}:{:02}:{:02}", hours, secs / 60 % 60, secs % 60),
+    }
+}
+
+fn view_tabs(current: View) -> Element<'static, Message> {
+    let tab = |label: &'static str, view: View| {
+        button(text(label)).on_press_maybe((current != view).then_some(Message::ViewSelected(view)))
+    };
+    row![
+        tab("library", View::Library),
+        tab("queue", View::Queue),
+        tab("suspicious files", View::Suspicious),
+        tab("loudness", View::Loudness),
+        tab("inbox", View::Inbox),
+        tab("organize", View::Organize),
+        tab("equalizer", View::Equalizer),
+        tab("effects", View::Effects),
+        tab("albums", View::Albums),
+        tab("incomplete albums", View::Incomplete),
+        tab("settings", View::Settings),
+    ]
+    .into()
+}
+
+/// lossless files whose spectrum looks like it came from a lossy source
+fn suspicious_files(songs: &[Song], pending: usize) -> Element<'static, Message> {
+    let status = if pending > 0 {
+        text(format!("analyzing, {} files left", pending))
+    } else {
+        text("")
+    };
+    let analyze = button(text("analyze library"))
+        .on_press_maybe((pending == 0).then_some(Message::AnalyzeSpectrum));
+    let suspicious = songs
+        .iter()
+        .filter_map(|s| s.spectrum.filter(|a| a.verdict == Verdict::Lossy).map(|a| (s, a)))
+        .map(|(song, analysis)| {
+            row![
+                text(song.name.clone().unwrap_or_default()).width(200.0),
+                text(song.track_artist.clone().unwrap_or_default()).width(150.0),
+                text(format!("{:.1} kHz", analysis.cutoff_hz / 1000.0)).width(80.0),
+                text(analysis.likely_source()),
+            ]
+            .into()
+        });
+    column![row![analyze, status], scrollable(column(suspicious))].into()
+}
+
+/// loudness of every analyzed song and the gain that brings it to the reference level
+fn loudness_view(songs: &[Song], pending: usize) -> Element<'static, Message> {
+    let status = if pending > 0 {
+        text(format!("analyzing, {} files left", pending))
+    } else {
+        text("")
+    };
+    let analyze = button(text("analyze loudness"))
+        .on_press_maybe((pending == 0).then_some(Message::AnalyzeLoudness));
+    let analyzed = songs
+        .iter()
+        .filter_map(|s| s.loudness.map(|l| (s, l)))
+        .map(|(song, loudness)| {
+            let album = match loudness.album_lufs {
+                Some(lufs) => format!("album {:.1} LUFS", lufs),
+                None => String::new(),
+            };
+            row![
+                text(song.name.clone().unwrap_or_default()).width(200.0),
+                text(song.track_artist.clone().unwrap_or_default()).width(150.0),
+                text(format!("{:.1} LUFS", loudness.track_lufs)).width(100.0),
+                text(format!("peak {:.1} dBTP", 20.0 * loudness.track_peak.log10())).width(120.0),
+                text(format!("{:+.1} dB", loudness::REFERENCE_LUFS - loudness.track_lufs))
+                    .width(80.0),
+                text(album),
+            ]
+            .into()
+        });
+    column![row![analyze, status], scrollable(column(analyzed))].into()
+}
+
+/// new downloads, with a tag editor for the selected one and where it will end up
+fn inbox_view(
+    inbox: &[Song],
+    selected: Option<&PathBuf>,
+    error: Option<&str>,
+    config: &Config,
+) -> Element<'static, Message> {
+    if config.downloads_dir.is_none() {
+        return text("set downloads_dir in the config to use the inbox").into();
+    }
+    let files = inbox.iter().map(|song| {
+        let label = song
+            .path
+            .file_name()
+            .map(|f| f.to_string_lossy().to_string())
+            .unwrap_or_default();
+        button(text(label))
+            .on_press_maybe(
+                (selected != Some(&song.path)).then(|| Message::InboxSelected(song.path.clone())),
+            )
+            .into()
+    });
+    let editor: Element<'static, Message> =
+        match selected.and_then(|p| inbox.iter().find(|s| &s.path == p)) {
+            Some(song) => {
+                let fields = TagField::ALL.into_iter().map(|field| {
+                    row![
+                        text(field.label()).width(100.0),
+                        text_input(field.label(), &field.get(song))
+                            .on_input(move |value| Message::InboxEdited(field, value)),
+                    ]
+                    .into()
+                });
+                let target = config
+                    .library_dir
+                    .join(template::render(&config.library_template, song));
+                column![
+                    column(fields),
+                    text(format!("-> {}", target.display())),
+                    button(text("accept")).on_press(Message::InboxAccept),
+                    text(error.unwrap_or_default().to_string()),
+                ]
+                .into()
+            }
+            None => text(format!("{} new files", inbox.len())).into(),
+        };
+    row![scrollable(column(files)).width(300.0), editor].into()
+}
+
+/// preview of the reorganize command, nothing moves until apply is pressed
+fn organize_view(
+    plan: Option<&[PlannedMove]>,
+    errors: &[String],
+    config: &Config,
+) -> Element<'static, Message> {
+    let relative = |p: &PathBuf| {
+        p.strip_prefix(&config.library_dir)
+            .unwrap_or(p)
+            .display()
+            .to_string()
+    };
+    let moves = plan.unwrap_or_default().iter().map(|m| {
+        row![
+            text(relative(&m.from)).width(400.0),
+            text("->").width(30.0),
+            text(relative(&m.to)),
+        ]
+        .into()
+    });
+    let status = match plan {
+        Some(plan) => format!("{} files to move", plan.len()),
+        None => format!("layout: {}", config.library_template),
+    };
+    column![
+        row![
+            button(text("preview")).on_press(Message::OrganizePreview),
+            button(text("apply")).on_press_maybe(
+                plan.is_some_and(|p| !p.is_empty()).then_some(Message::OrganizeApply)
+            ),
+            text(status),
+        ],
+        column(errors.iter().map(|e| text(e.clone()).into())),
+        scrollable(column(moves)),
+    ]
+    .into()
+}
+
+/// preset list, bypass and the bands of the selected preset
+fn equalizer_view(
+    config: &Config,
+    selected: Option<EqPreset>,
+    import_path: &str,
+    error: Option<&str>,
+) -> Element<'static, Message> {
+    let bypass = button(text(if config.eq_bypass { "bypassed" } else { "bypass" }))
+        .on_press_maybe(selected.is_some().then_some(Message::EqBypassToggled));
+    let selected_name = selected.as_ref().map(|p| p.name.clone());
+    let presets = config.all_eq_presets().into_iter().map(|preset| {
+        let is_selected = selected_name.as_ref() == Some(&preset.name);
+        button(text(preset.name.clone()))
+            .on_press_maybe((!is_selected).then(|| Message::EqPresetSelected(preset.name)))
+            .into()
+    });
+    let bands: Element<'static, Message> = match selected.map(|p| p.mode) {
+        Some(EqMode::Graphic { gains }) => row(GRAPHIC_BANDS.iter().zip(gains).enumerate().map(
+            |(band, (freq, gain))| {
+                let label = if *freq >= 1000.0 {
+                    format!("{}k", freq / 1000.0)
+                } else {
+                    format!("{}", freq.round())
+                };
+                column![
+                    text(format!("{:+.1}", gain)),
+                    vertical_slider(-GRAPHIC_RANGE..=GRAPHIC_RANGE, gain, move |g| {
+                        Message::EqGainChanged(band, g)
+                    })
+                    .step(0.5)
+                    .height(150.0)
+                    .on_release(Message::SaveConfig),
+                    text(label),
+                ]
+                .width(50.0)
+                .into()
+            },
+        ))
+        .into(),
+        Some(EqMode::Parametric { bands }) => column(bands.into_iter().map(|band| {
+            text(format!(
+                "{:?} {} Hz {:+.1} dB q {:.2}",
+                band.kind, band.freq, band.gain, band.q
+            ))
+            .into()
+        }))
+        .into(),
+        None => text("no equalizer").into(),
+    };
+    column![
+        bypass,
+        row![scrollable(column(presets)).width(200.0), bands],
+        row![
+            text_input("path to an AutoEQ ParametricEQ.txt", import_path)
+                .on_input(Message::EqImportPathChanged)
+                .on_submit(Message::EqImport),
+            button(text("import")).on_press(Message::EqImport),
+        ],
+        text(error.unwrap_or_default().to_string()),
+    ]
+    .into()
+}
+
+/// the effect chain in order, with a slider for every parameter
+fn effects_view(effects: &[EffectInfo]) -> Element<'static, Message> {
+    let add = row(dsp::EFFECTS.iter().map(|name| {
+        button(text(format!("add {}", name)))
+            .on_press(Message::Dsp(DspCommand::Add(name.to_string())))
+            .into()
+    }));
+    let chain = effects.iter().enumerate().map(|(index, effect)| {
+        let params = effect.params.iter().map(|param| {
+            let name = param.name.clone();
+            row![
+                text(param.name.clone()).width(100.0),
+                slider(param.min..=param.max, param.value, move |value| {
+                    Message::Dsp(DspCommand::SetParam(index, name.clone(), value))
+                })
+                .step((param.max - param.min) / 200.0)
+                .width(300.0)
+                .on_release(Message::SaveConfig),
+                text(format!("{:.2}", param.value)),
+            ]
+            .into()
+        });
+        column![
+            row![
+                text(effect.name.clone()).width(150.0),
+                button(text("up")).on_press_maybe(
+                    (index > 0).then(|| Message::Dsp(DspCommand::Move(index, index - 1)))
+                ),
+                button(text("remove")).on_press(Message::Dsp(DspCommand::Remove(index))),
+            ],
+            column(params),
+        ]
+        .into()
+    });
+    column![add, scrollable(column(chain))].into()
+}
+
+/// the output device to play on. the one chosen is remembered by name, the default one is
+/// used while it isn't plugged in. playing into nothing or into a WAV file is for trying
+/// things out without a sound card, a pipe feeds PCM to other programs. streaming serves
+/// what's playing to other machines as well, whatever it plays on.
+fn settings_view(
+    config: &Config,
+    devices: &[String],
+    in_use: Option<&str>,
+    wav_path: &str,
+    pipe_path: &str,
+    stream_port: &str,
+    streaming: Option<u16>,
+) -> Element<'static, Message> {
+    // the device that is chosen, if a device is what's chosen
+    let chosen = match config.output {
+        OutputKind::Device => Some(config.output_device.as_deref()),
+        _ => None,
+    };
+    let default = button(text("default device"))
+        .on_press_maybe((chosen != Some(None)).then_some(Message::OutputDeviceSelected(None)));
+    let devices = devices.iter().map(|name| {
+        let label = if in_use == Some(name.as_str()) {
+            format!("{} (playing)", name)
+        } else {
+            name.clone()
+        };
+        button(text(label))
+            .on_press_maybe(
+                (chosen != Some(Some(name.as_str())))
+                    .then(|| Message::OutputDeviceSelected(Some(name.clone()))),
+            )
+            .into()
+    });
+    let null = OutputKind::Null { realtime: false };
+    let null = button(text("no output"))
+        .on_press_maybe((config.output != null).then_some(Message::OutputSelected(null)));
+    let wav = OutputKind::Wav {
+        path: PathBuf::from(wav_path.trim()),
+        realtime: false,
+    };
+    let wav = row![
+        text_input("path of a WAV file to play into", wav_path)
+            .on_input(Message::OutputWavPathChanged)
+            .on_submit(Message::OutputSelected(wav.clone()))
+            .width(400.0),
+        button(text("play into file"))
+            .on_press_maybe((!wav_path.trim().is_empty()).then_some(Message::OutputSelected(wav))),
+    ];
+    // the sample format and rate are set in the config file
+    let pipe_path = pipe_path.trim();
+    let pipe = config
+        .output
+        .pipe_to((!pipe_path.is_empty()).then(|| PathBuf::from(pipe_path)));
+    let pipe_label = if pipe_path.is_empty() { "play into stdout" } else { "play into pipe" };
+    let pipe = row![
+        text_input("path of a fifo to write raw PCM to, empty for stdout", pipe_path)
+            .on_input(Message::OutputPipePathChanged)
+            .on_submit(Message::OutputSelected(pipe.clone()))
+            .width(400.0),
+        button(text(pipe_label)).on_press(Message::OutputSelected(pipe)),
+    ];
+    let stream = match streaming {
+        Some(port) => row![
+            text(format!("streaming FLAC at http://<this machine>:{}/", port)),
+            button(text("stop streaming")).on_press(Message::StreamToggled),
+        ],
+        None => row![
+            text_input("port", stream_port)
+                .on_input(Message::StreamPortChanged)
+                .on_submit(Message::StreamToggled)
+                .width(100.0),
+            button(text("start streaming")).on_press(Message::StreamToggled),
+        ],
+    };
+    column![
+        text(format!("output: {}", in_use.unwrap_or("none"))),
+        row![default, null, button(text("refresh")).on_press(Message::ListOutputDevices)],
+        wav,
+        pipe,
+        stream,
+        scrollable(column(devices)),
+    ]
+    .into()
+}
+
+fn album_browser(albums: &[Album]) -> Element<'static, Message> {
+    scrollable(column(albums.iter().map(|album| {
+        let badge = if album.is_complete() {
+            text("")
+        } else {
+            text("incomplete")
+        };
+        row![
+            text(album.name.clone()).width(200.0),
+            text(album.artist.clone()).width(150.0),
+            text(format!("{} tracks", album.tracks)).width(80.0),
+            badge,
+        ]
+        .into()
+    })))
+    .into()
+}
+
+/// every album with tracks or discs missing, and which ones
+fn incomplete_albums(albums: &[Album]) -> Element<'static, Message> {
+    let incomplete: Vec<&Album> = albums.iter().filter(|a| !a.is_complete()).collect();
+    column![
+        text(format!("{} incomplete albums", incomplete.len())),
+        scrollable(column(incomplete.into_iter().map(|album| {
+            row![
+                text(album.name.clone()).width(200.0),
+                text(album.artist.clone()).width(150.0),
+                text(album.describe_gaps()),
+            ]
+            .into()
+        }))),
+    ]
+    .into()
+}
+
+fn now_playing(song: Option<&Song>, error: Option<&str>) -> Element<'static, Message> {
+    if let Some(error) = error {
+        return text(format!("error: {}", error)).into();
+    }
+    match song {
+        Some(song) => text(format!(
+            "now playing: {} - {}",
+            song.name.clone().unwrap_or_default(),
+            song.track_artist.clone().unwrap_or_default()
+        ))
+        .into(),
+        None => text("now playing").into(),
+    }
+}
+
+/// everything in the queue, click an entry to play it
+fn queue_view(
+    queue: &[Song],
+    current: Option<usize>,
+    order: &[usize],
+) -> Element<'static, Message> {
+    scrollable(column(order.iter().map(|&index| {
+        let song = &queue[index];
+        let marker = if current == Some(index) {
+            ">"
+        } else {
+            ""
+        };
+        button(row![
+            text(marker).width(20.0),
+            text(song.name.clone().unwrap_or_default()).width(200.0),
+            text(song.track_artist.clone().unwrap_or_default()).width(150.0),
+        ])
+        .on_press(Message::QueueJump(index))
+        .into()
+    })))
+    .into()
 }
 
-fn play_controls(playing: bool) -> Element<'static, Message> {
+fn play_controls(playing: bool, config: &Config) -> Element<'static, Message> {
     let next_handle = svg::Handle::from_memory(NEXT_ICON);
     let prev_handle = svg::Handle::from_memory(PREV_ICON);
     let play_handle = svg::Handle::from_memory(PLAY_ICON);
@@ -303,6 +1477,25 @@ fn play_controls(playing: bool) -> Element<'static, Message> {
         button(svg(prev_handle).width(25).height(25)).on_press(Message::Prev),
         play_btn,
         button(svg(next_handle).width(25).height(25)).on_press(Message::Next),
+        button(text(format!("shuffle: {:?}", config.shuffle).to_lowercase()))
+            .on_press(Message::ShuffleToggled),
+        button(text(format!("repeat: {:?}", config.repeat).to_lowercase()))
+            .on_press(Message::RepeatToggled),
+        button(text(if config.muted { "unmute" } else { "mute" })).on_press(Message::MuteToggled),
+        slider(0.0..=1.0, config.volume, Message::VolumeChanged)
+            .step(0.01)
+            .width(150.0)
+            .on_release(Message::SaveConfig),
+        text(format!("{:.2}x", config.speed)),
+        slider(MIN_SPEED..=MAX_SPEED, config.speed, Message::SpeedChanged)
+            .step(0.05)
+            .width(100.0)
+            .on_release(Message::SaveConfig),
+        text(format!("{:+} st", config.pitch_semitones)),
+        slider(-MAX_PITCH..=MAX_PITCH, config.pitch_semitones, Message::PitchChanged)
+            .step(1.0)
+            .width(100.0)
+            .on_release(Message::SaveConfig),
     ]
     .into()
 }
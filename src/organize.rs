@@ -0,0 +1,220 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{config::Config, inbox::move_file, template, Song};
+
+/// sidecars that belong to one track and share its file stem, like `01 song.lrc`
+const TRACK_SIDECARS: [&str; 2] = ["lrc", "cue"];
+/// sidecars that belong to the whole folder, like `cover.jpg`
+const FOLDER_SIDECARS: [&str; 7] = ["jpg", "jpeg", "png", "webp", "gif", "cue", "log"];
+
+/// a single file move the organizer wants to do
+#[derive(Debug, Clone)]
+pub struct PlannedMove {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    /// `true` for audio files, `false` for covers, lyrics and other sidecars
+    pub audio: bool,
+    /// the audio file a track sidecar belongs to. it only moves if that one did.
+    pub sidecar_of: Option<PathBuf>,
+}
+
+/// work out where every song (and its sidecars) should go according to the library template.
+/// nothing is touched on disk, this is the dry run shown before [`apply`].
+pub fn plan(songs: &[Song], config: &Config) -> Vec<PlannedMove> {
+    let mut moves = Vec::new();
+    let mut claimed = HashSet::new();
+    // where the audio files of each source folder are going, for the folder sidecars
+    let mut folder_targets: HashMap<PathBuf, PathBuf> = HashMap::new();
+
+    for song in songs {
+        let wanted = config
+            .library_dir
+            .join(template::render(&config.library_template, song));
+        if wanted == song.path {
+            claimed.insert(wanted);
+            continue;
+        }
+        let sidecars = track_sidecars(&song.path);
+        let to = unique_target(wanted, &song.path, &sidecars, &claimed);
+        claimed.insert(to.clone());
+
+        let from_dir = song
+            .path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        let to_dir = to.parent().map(Path::to_path_buf).unwrap_or_default();
+        folder_targets.entry(from_dir).or_insert(to_dir);
+        moves.push(PlannedMove {
+            from: song.path.clone(),
+            to: to.clone(),
+            audio: true,
+            sidecar_of: None,
+        });
+        for sidecar in sidecars {
+            let sidecar_to = sidecar_target(&to, &sidecar);
+            claimed.insert(sidecar_to.clone());
+            moves.push(PlannedMove {
+                from: sidecar,
+                to: sidecar_to,
+                audio: false,
+                sidecar_of: Some(song.path.clone()),
+            });
+        }
+    }
+
+    let moving: HashSet<PathBuf> = moves.iter().map(|m| m.from.clone()).collect();
+    for (from_dir, to_dir) in folder_targets {
+        if from_dir == to_dir {
+            continue;
+        }
+        for sidecar in folder_sidecars(&from_dir) {
+            if moving.contains(&sidecar) {
+                continue;
+            }
+            let name = sidecar.file_name().unwrap_or_default();
+            let to = unique_target(to_dir.join(name), &sidecar, &[], &claimed);
+            claimed.insert(to.clone());
+            moves.push(PlannedMove {
+                from: sidecar,
+                to,
+                audio: false,
+                sidecar_of: None,
+            });
+        }
+    }
+    moves
+}
+
+/// carry out a plan from [`plan`]. returns the moves that worked and an error message for
+/// each one that didn't. the sidecars of a track that couldn't be moved stay with it.
+/// folders below `root` left empty behind are removed.
+pub fn apply(moves: Vec<PlannedMove>, root: &Path) -> (Vec<PlannedMove>, Vec<String>) {
+    let mut done = Vec::new();
+    let mut errors = Vec::new();
+    let mut emptied = HashSet::new();
+    let mut failed = HashSet::new();
+    for planned in moves {
+        if matches!(&planned.sidecar_of, Some(audio) if failed.contains(audio)) {
+            continue;
+        }
+        let result = if planned.to.exists() && !same_file(&planned.to, &planned.from) {
+            Err(format!("{:?} already exists", planned.to))
+        } else {
+            planned
+                .to
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| move_file(&planned.from, &planned.to))
+                .map_err(|e| format!("failed to move {:?}: {}", planned.from, e))
+        };
+        match result {
+            Ok(()) => {
+                if let Some(parent) = planned.from.parent() {
+                    emptied.insert(parent.to_path_buf());
+                }
+                done.push(planned);
+            }
+            Err(e) => {
+                if planned.audio {
+                    failed.insert(planned.from);
+                }
+                errors.push(e);
+            }
+        }
+    }
+    for dir in emptied {
+        remove_empty_dirs(&dir, root);
+    }
+    (done, errors)
+}
+
+/// `wanted`, or `wanted` with ` (2)`, ` (3)`, ... added to the stem if something else is
+/// already there or another move in the plan got there first. the track `sidecars` of
+/// `from` keep its stem, so their names have to be free as well.
+fn unique_target(
+    wanted: PathBuf,
+    from: &Path,
+    sidecars: &[PathBuf],
+    claimed: &HashSet<PathBuf>,
+) -> PathBuf {
+    let taken_by =
+        |p: &Path, from: &Path| claimed.contains(p) || (p.exists() && !same_file(p, from));
+    let taken =
+        |p: &Path| taken_by(p, from) || sidecars.iter().any(|s| taken_by(&sidecar_target(p, s), s));
+    if !taken(&wanted) {
+        return wanted;
+    }
+    let stem = wanted
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let ext = wanted
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()));
+    (2..)
+        .map(|n| wanted.with_file_name(format!("{} ({}){}", stem, n, ext.as_deref().unwrap_or(""))))
+        .find(|p| !taken(p))
+        .expect("ran out of numbers")
+}
+
+/// where the track sidecar `sidecar` goes when its audio file goes to `audio`
+fn sidecar_target(audio: &Path, sidecar: &Path) -> PathBuf {
+    audio.with_extension(sidecar.extension().unwrap_or_default())
+}
+
+/// two paths point at the same file, which happens on case insensitive file systems
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| extensions.contains(&e.to_lowercase().as_str()))
+}
+
+/// files next to `audio` with the same stem and a track sidecar extension
+fn track_sidecars(audio: &Path) -> Vec<PathBuf> {
+    let (Some(dir), Some(stem)) = (audio.parent(), audio.file_stem()) else {
+        return Vec::new();
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p != audio && p.file_stem() == Some(stem))
+        .filter(|p| has_extension(p, &TRACK_SIDECARS))
+        .collect()
+}
+
+/// covers and other files in `dir` that belong to the folder as a whole
+fn folder_sidecars(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.is_file() && has_extension(p, &FOLDER_SIDECARS))
+        .collect()
+}
+
+/// remove `dir` and its parents up to `root` for as long as they are empty
+fn remove_empty_dirs(dir: &Path, root: &Path) {
+    let mut dir = Some(dir);
+    while let Some(d) = dir {
+        if d == root || !d.starts_with(root) || fs::remove_dir(d).is_err() {
+            break;
+        }
+        dir = d.parent();
+    }
+}
@@ -0,0 +1,457 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+use rodio::{
+    cpal, cpal::traits::HostTrait, source::UniformSourceIterator, DeviceTrait, OutputStream, Sink,
+};
+use serde::{Deserialize, Serialize};
+
+/// format the null and WAV backends run at
+const SAMPLE_RATE: u32 = 44100;
+const CHANNELS: u16 = 2;
+/// the backends without a sound card take samples in pieces this long
+const CHUNK: Duration = Duration::from_millis(10);
+/// how often the WAV header is brought up to date, so a killed process still leaves a
+/// readable file
+const WAV_FLUSH: Duration = Duration::from_secs(1);
+/// how long a pipe is waited on, for a reader or for room in it, before checking whether
+/// the output is still wanted
+const PIPE_POLL: Duration = Duration::from_millis(50);
+
+/// where the engine's samples go. everything but `Device` works without a sound card, for
+/// running the whole player on machines without one.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum OutputKind {
+    /// the sound card named by `output_device` in the config
+    #[default]
+    Device,
+    /// throw the samples away. silence goes at the speed it would play at, music as fast
+    /// as it can be decoded unless `realtime` is set.
+    Null {
+        #[serde(default)]
+        realtime: bool,
+    },
+    /// write everything that would have been heard to a 44.1 kHz stereo float WAV file,
+    /// at the same pace as `Null`
+    Wav {
+        path: PathBuf,
+        #[serde(default)]
+        realtime: bool,
+    },
+    /// write raw interleaved PCM to a named pipe, or to stdout without a path, for tools like
+    /// snapcast. tracks at another rate are resampled. a pipe only takes as much as its
+    /// reader reads, so this goes at the reader's pace unless `realtime` is set.
+    Pipe {
+        path: Option<PathBuf>,
+        #[serde(default)]
+        format: PcmFormat,
+        #[serde(default = "default_pipe_rate")]
+        sample_rate: u32,
+        #[serde(default = "default_pipe_channels")]
+        channels: u16,
+        #[serde(default)]
+        realtime: bool,
+    },
+}
+
+impl OutputKind {
+    /// a pipe to `path`, or to stdout, in the format this is in if it's a pipe already
+    pub fn pipe_to(&self, path: Option<PathBuf>) -> OutputKind {
+        match self {
+            OutputKind::Pipe {
+                format,
+                sample_rate,
+                channels,
+                realtime,
+                ..
+            } => OutputKind::Pipe {
+                path,
+                format: *format,
+                sample_rate: *sample_rate,
+                channels: *channels,
+                realtime: *realtime,
+            },
+            _ => OutputKind::Pipe {
+                path,
+                format: PcmFormat::default(),
+                sample_rate: default_pipe_rate(),
+                channels: default_pipe_channels(),
+                realtime: false,
+            },
+        }
+    }
+}
+
+/// what snapcast expects unless told otherwise
+fn default_pipe_rate() -> u32 {
+    48000
+}
+
+fn default_pipe_channels() -> u16 {
+    2
+}
+
+/// sample format of [`OutputKind::Pipe`], all little endian
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PcmFormat {
+    #[default]
+    S16le,
+    /// 24 bits packed into 3 bytes
+    S24le,
+    S32le,
+    F32le,
+}
+
+impl PcmFormat {
+    fn write(self, sample: f32, out: &mut Vec<u8>) {
+        let sample = sample.clamp(-1.0, 1.0);
+        match self {
+            PcmFormat::S16le => out.extend(((sample * i16::MAX as f32) as i16).to_le_bytes()),
+            PcmFormat::S24le => {
+                out.extend(&((sample * 8_388_607.0) as i32).to_le_bytes()[..3]);
+            }
+            PcmFormat::S32le => {
+                out.extend(((sample as f64 * i32::MAX as f64) as i32).to_le_bytes())
+            }
+            PcmFormat::F32le => out.extend(sample.to_le_bytes()),
+        }
+    }
+}
+
+/// names of the output devices that can be chosen
+pub fn output_devices() -> Vec<String> {
+    match cpal::default_host().output_devices() {
+        Ok(devices) => devices.filter_map(|d| d.name().ok()).collect(),
+        Err(e) => {
+            println!("error: could not list output devices: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// an open output. dropping it closes the device, or stops the backend thread and waits for
+/// it to end.
+pub struct Output {
+    pub name: String,
+    _stream: Option<OutputStream>,
+    worker: Option<JoinHandle<()>>,
+    /// the worker waits for as long as its reader likes, a pipe with nobody on the other end
+    blocks: bool,
+    /// tells the worker to stop, even while it's waiting on a pipe
+    closed: Arc<AtomicBool>,
+}
+
+impl Output {
+    /// whether the output not taking samples means it's broken. a pipe waits for its reader
+    /// and only counts as broken once it gave up.
+    pub fn may_stall(&self) -> bool {
+        self.blocks && self.worker.as_ref().is_some_and(|w| !w.is_finished())
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+        if let Some(worker) = self.worker.take() {
+            if worker.join().is_err() {
+                println!("error: output {:?} panicked", self.name);
+            }
+        }
+    }
+}
+
+/// open an output of the given kind, and a sink playing on it. `device` is the name of the
+/// sound card to use for [`OutputKind::Device`], `None` for the default one.
+pub fn open(kind: &OutputKind, device: Option<&str>) -> Result<(Output, Sink), String> {
+    let closed = Arc::new(AtomicBool::new(false));
+    match kind {
+        OutputKind::Device => open_device(device),
+        OutputKind::Null { realtime } => {
+            let (sink, worker) = spawn_backend(
+                "null",
+                SAMPLE_RATE,
+                CHANNELS,
+                *realtime,
+                closed.clone(),
+                |_| Ok(()),
+            );
+            Ok((output("null".to_string(), worker, false, closed), sink))
+        }
+        OutputKind::Wav { path, realtime } => {
+            let spec = WavSpec {
+                channels: CHANNELS,
+                sample_rate: SAMPLE_RATE,
+                bits_per_sample: 32,
+                sample_format: SampleFormat::Float,
+            };
+            let mut writer = WavWriter::create(path, spec)
+                .map_err(|e| format!("could not create {:?}: {}", path, e))?;
+            let mut flushed = Instant::now();
+            let name = format!("wav file {:?}", path);
+            let write = move |chunk: &[f32]| -> Result<(), String> {
+                for &sample in chunk {
+                    writer.write_sample(sample).map_err(|e| e.to_string())?;
+                }
+                if flushed.elapsed() > WAV_FLUSH {
+                    writer.flush().map_err(|e| e.to_string())?;
+                    flushed = Instant::now();
+                }
+                Ok(())
+            };
+            let (sink, worker) = spawn_backend(
+                &name,
+                SAMPLE_RATE,
+                CHANNELS,
+                *realtime,
+                closed.clone(),
+                write,
+            );
+            Ok((output(name, worker, false, closed), sink))
+        }
+        OutputKind::Pipe {
+            path,
+            format,
+            sample_rate,
+            channels,
+            realtime,
+        } => {
+            let name = match path {
+                Some(path) => format!("pipe {:?}", path),
+                None => "stdout".to_string(),
+            };
+            let (path, format) = (path.clone(), *format);
+            let mut pipe = None;
+            let mut bytes = Vec::new();
+            let stop = closed.clone();
+            let write = move |chunk: &[f32]| -> Result<(), String> {
+                // opening a fifo waits for a reader, which is why it's done here
+                let out = match &mut pipe {
+                    Some(out) => out,
+                    None => match open_pipe(path.as_deref(), &stop).map_err(|e| e.to_string())? {
+                        Some(out) => pipe.insert(out),
+                        // closed before anyone came to read
+                        None => return Ok(()),
+                    },
+                };
+                bytes.clear();
+                chunk.iter().for_each(|&s| format.write(s, &mut bytes));
+                match write_pipe(out, &bytes, &stop) {
+                    Ok(()) => Ok(()),
+                    // the reader went away, wait for the next one
+                    Err(e) if e.kind() == ErrorKind::BrokenPipe => {
+                        pipe = None;
+                        Ok(())
+                    }
+                    Err(e) => Err(e.to_string()),
+                }
+            };
+            let channels = (*channels).max(1);
+            let (sink, worker) = spawn_backend(
+                &name,
+                *sample_rate,
+                channels,
+                *realtime,
+                closed.clone(),
+                write,
+            );
+            Ok((output(name, worker, true, closed), sink))
+        }
+    }
+}
+
+fn output(name: String, worker: JoinHandle<()>, blocks: bool, closed: Arc<AtomicBool>) -> Output {
+    Output {
+        name,
+        _stream: None,
+        worker: Some(worker),
+        blocks,
+        closed,
+    }
+}
+
+/// the fifo at `path`, or stdout. a fifo is only open once someone reads it, `None` means
+/// `closed` was set while waiting for that.
+#[cfg(unix)]
+fn open_pipe(path: Option<&Path>, closed: &AtomicBool) -> io::Result<Option<File>> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let Some(path) = path else {
+        return stdout_for_pcm().map(Some);
+    };
+    loop {
+        // without a reader this fails right away, where a plain open would wait for one
+        let file = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path);
+        match file {
+            Err(e) if e.raw_os_error() == Some(libc::ENXIO) => {}
+            file => return file.map(Some),
+        }
+        if closed.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        thread::sleep(PIPE_POLL);
+    }
+}
+
+#[cfg(not(unix))]
+fn open_pipe(path: Option<&Path>, _: &AtomicBool) -> io::Result<Option<Box<dyn Write + Send>>> {
+    match path {
+        Some(path) => Ok(Some(Box::new(OpenOptions::new().write(true).open(path)?))),
+        None => Ok(Some(Box::new(stdout_for_pcm()?))),
+    }
+}
+
+/// write all of `bytes` to a pipe, a bit at a time whenever there is room, so it never
+/// waits on a reader that stopped reading for longer than [`PIPE_POLL`]. what's left is
+/// dropped once `closed` is set.
+#[cfg(unix)]
+fn write_pipe(out: &mut File, mut bytes: &[u8], closed: &AtomicBool) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    while !bytes.is_empty() && !closed.load(Ordering::Relaxed) {
+        let mut poll = libc::pollfd {
+            fd: out.as_raw_fd(),
+            events: libc::POLLOUT,
+            revents: 0,
+        };
+        // SAFETY: a single pollfd for an fd `out` keeps open
+        match unsafe { libc::poll(&mut poll, 1, PIPE_POLL.as_millis() as i32) } {
+            0 => continue,
+            ready if ready < 0 => match io::Error::last_os_error() {
+                e if e.kind() == ErrorKind::Interrupted => continue,
+                e => return Err(e),
+            },
+            _ => {}
+        }
+        // no more than a pipe with room takes at once, stdout isn't non blocking
+        match out.write(&bytes[..bytes.len().min(libc::PIPE_BUF)]) {
+            Ok(written) => bytes = &bytes[written..],
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn write_pipe(out: &mut impl Write, bytes: &[u8], _: &AtomicBool) -> io::Result<()> {
+    out.write_all(bytes)
+}
+
+/// the real stdout, for writing PCM to. the first call moves stdout over to stderr, so the
+/// log lines `println!` writes don't end up in the audio.
+#[cfg(unix)]
+fn stdout_for_pcm() -> io::Result<File> {
+    use std::os::fd::{FromRawFd, OwnedFd};
+
+    static PCM: OnceLock<Result<OwnedFd, i32>> = OnceLock::new();
+    let pcm = PCM.get_or_init(|| {
+        let _ = io::stdout().flush();
+        // SAFETY: plain fd juggling on fds that stay open for the whole process. the dup
+        // is owned by nothing else.
+        unsafe {
+            let pcm = libc::dup(libc::STDOUT_FILENO);
+            if pcm < 0 || libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+                return Err(io::Error::last_os_error().raw_os_error().unwrap_or(0));
+            }
+            Ok(OwnedFd::from_raw_fd(pcm))
+        }
+    });
+    match pcm {
+        Ok(fd) => Ok(File::from(fd.try_clone()?)),
+        Err(e) => Err(io::Error::from_raw_os_error(*e)),
+    }
+}
+
+#[cfg(not(unix))]
+fn stdout_for_pcm() -> io::Result<io::Stdout> {
+    Ok(io::stdout())
+}
+
+fn open_device(name: Option<&str>) -> Result<(Output, Sink), String> {
+    let host = cpal::default_host();
+    let device = match name {
+        Some(name) => host
+            .output_devices()
+            .map_err(|e| format!("could not list output devices: {}", e))?
+            .find(|d| d.name().is_ok_and(|n| n == name))
+            .ok_or(format!("output device {:?} not found", name))?,
+        None => host
+            .default_output_device()
+            .ok_or("there is no default output device")?,
+    };
+    let name = device.name().unwrap_or_default();
+    let (stream, handle) = OutputStream::try_from_device(&device)
+        .map_err(|e| format!("could not open output device {:?}: {}", name, e))?;
+    let sink = Sink::try_new(&handle)
+        .map_err(|e| format!("could not play on output device {:?}: {}", name, e))?;
+    let output = Output {
+        name,
+        _stream: Some(stream),
+        worker: None,
+        blocks: false,
+        closed: Arc::default(),
+    };
+    Ok((output, sink))
+}
+
+/// a sink whose samples are resampled to `sample_rate` and `channels`, taken by a thread of
+/// our own and handed to `write` a chunk at a time, instead of going to a sound card
+fn spawn_backend(
+    name: &str,
+    sample_rate: u32,
+    channels: u16,
+    realtime: bool,
+    closed: Arc<AtomicBool>,
+    mut write: impl FnMut(&[f32]) -> Result<(), String> + Send + 'static,
+) -> (Sink, JoinHandle<()>) {
+    let (sink, queue) = Sink::new_idle();
+    let mut samples: UniformSourceIterator<_, f32> =
+        UniformSourceIterator::new(queue, channels, sample_rate);
+    let chunk_len = (CHUNK.as_secs_f64() * sample_rate as f64) as usize * channels as usize;
+    let thread_name = name.to_string();
+    let worker = thread::Builder::new()
+        .name(format!("output {}", name))
+        .spawn(move || {
+            let mut chunk = Vec::with_capacity(chunk_len);
+            let mut deadline = Instant::now();
+            loop {
+                chunk.clear();
+                chunk.extend(samples.by_ref().take(chunk_len));
+                if let Err(e) = write(&chunk) {
+                    println!("error: output {:?} failed: {}", thread_name, e);
+                    return;
+                }
+                // the output is closed, or the sink is gone and its last source is done
+                if closed.load(Ordering::Relaxed) || chunk.len() < chunk_len {
+                    return;
+                }
+                // silence is what a paused or empty sink gives, so it can't be sped through
+                if realtime || chunk.iter().all(|s| *s == 0.0) {
+                    deadline += CHUNK;
+                    match deadline.checked_duration_since(Instant::now()) {
+                        Some(wait) => thread::sleep(wait),
+                        None => deadline = Instant::now(),
+                    }
+                } else {
+                    deadline = Instant::now();
+                }
+            }
+        })
+        .expect("failed to spawn output thread");
+    (sink, worker)
+}
@@ -1,28 +1,222 @@
 use std::{
+    collections::HashMap,
     fmt::Debug,
-    time::Duration,
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    sync::{
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
 };
 
-use rodio::{OutputStream, Sink};
+use iced::{futures::SinkExt, stream, Subscription};
+use rodio::{source::SeekError, Sink};
+use tokio::sync::{
+    mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    Mutex,
+};
+
+use crate::{
+    config::Config,
+    dsp::{DspChain, DspCommand, EffectInfo},
+    eq::{EqPreset, Equalizer},
+    output::{self, output_devices, Output, OutputKind},
+    playback::{Crossfade, Fader, Playback, PlaybackSource, Track, PAUSE_FADE},
+    queue::{Queue, RepeatMode, ShuffleMode},
+    replaygain::ReplayGainSettings,
+    seeker::SeekPos,
+    session::SavedQueue,
+    stream::{Streamer, Tap, TapSlot},
+    stretch::Stretcher,
+    Song,
+};
+
+/// how often the engine checks for the end of a track and reports the position
+const TICK: Duration = Duration::from_millis(50);
+/// the position is only reported when it moved at least this much
+const POSITION_STEP: Duration = Duration::from_millis(100);
+/// how far into a track prev restarts it instead of going to the previous one
+const PREV_RESTARTS_AFTER: Duration = Duration::from_secs(3);
+/// playback that hasn't moved for this long means the output stopped taking samples
+const OUTPUT_STALL: Duration = Duration::from_secs(2);
+/// the quietest the volume slider goes before it reaches silence, in dB
+const VOLUME_RANGE_DB: f32 = 50.0;
+
+/// the factor the sink scales by for a volume slider position from 0 to 1. the slider is
+/// in dB so it's just as fine grained at low volumes as at high ones.
+fn volume_gain(volume: f32, muted: bool) -> f32 {
+    if muted || volume <= 0.0 {
+        return 0.0;
+    }
+    10f32.powf(VOLUME_RANGE_DB * (volume.min(1.0) - 1.0) / 20.0)
+}
 
+/// the length of `song`. the library knows it for most songs. for the others the decoder's
+/// guess does until the scan in the background gets to them, see
+/// [`PlayerMessage::SetDuration`]. `None` while neither knows.
+fn song_duration(song: &Song, decoder: Option<Duration>) -> Option<Duration> {
+    song.duration.or(decoder)
+}
 
+/// why a seek failed. rodio only says "An error occurred" for the errors our own source
+/// gives, the decoder's reason is inside.
+fn seek_error(e: SeekError) -> String {
+    match e {
+        SeekError::Other(e) => e.to_string(),
+        e => e.to_string(),
+    }
+}
+
+/// what listeners of the stream are shown for a song
+fn stream_title(song: &Song) -> String {
+    let title = song.name.clone().unwrap_or_else(|| {
+        let stem = song.path.file_stem().unwrap_or_default();
+        stem.to_string_lossy().into_owned()
+    });
+    match song.track_artist.as_ref().or(song.album_artist.as_ref()) {
+        Some(artist) => format!("{} - {}", artist, title),
+        None => title,
+    }
+}
+
+/// commands the engine accepts. anything that wants to control playback (the gui, scripts,
+/// remote controls) sends these through a [`PlayerManager`] or a clone of its sender.
+#[derive(Debug, Clone)]
+pub enum PlayerMessage {
+    Play,
+    Paus,
+    Stop,
+    Next,
+    Prev,
+    Seek(SeekPos),
+    /// add a song to the end of the queue, starting it if nothing is playing
+    Enqueue(Box<Song>),
+    /// play the queue entry at this index
+    Jump(usize),
+    SetCrossfade(Crossfade),
+    SetReplayGain(ReplayGainSettings),
+    /// run the equalizer with this preset, `None` to bypass it
+    SetEqualizer(Option<EqPreset>),
+    Dsp(DspCommand),
+    /// volume slider position from 0 to 1
+    SetVolume(f32),
+    SetMuted(bool),
+    /// tempo from `MIN_SPEED` to `MAX_SPEED`, without changing the pitch
+    SetSpeed(f32),
+    /// pitch shift in semitones, without changing the tempo
+    SetPitch(f32),
+    SetShuffle(ShuffleMode),
+    SetRepeat(RepeatMode),
+    /// play on the output device with this name, `None` for the default one
+    SetOutputDevice(Option<String>),
+    /// play on another kind of output, like a WAV file
+    SetOutput(OutputKind),
+    /// serve what's playing over http on this port, `None` to stop
+    SetStream(Option<u16>),
+    /// replace the queue with one saved earlier, and load its current entry paused where it
+    /// was left
+    Restore(Box<SavedQueue>),
+    /// the length of the file at this path was worked out, for queue entries that didn't
+    /// know it yet
+    SetDuration(PathBuf, Duration),
+    /// files were moved, from the keys to the values. queue entries follow them.
+    FilesMoved(HashMap<PathBuf, PathBuf>),
+}
+
+/// what the engine reports back
+#[derive(Debug, Clone)]
+pub enum PlayerEvent {
+    /// a track started playing, with its length if that's known
+    TrackStarted(Box<Song>, Option<Duration>),
+    /// a track was loaded without playing, like the one left playing last time
+    TrackLoaded(Box<Song>, Option<Duration>),
+    TrackEnded,
+    /// the length of the current track turned out to be this
+    DurationChanged(Duration),
+    PositionChanged(Duration),
+    /// playback was started (`true`) or paused/stopped (`false`)
+    PlayingChanged(bool),
+    /// the queue entries, the index of the current one and the indices in play order
+    QueueChanged(Vec<Song>, Option<usize>, Vec<usize>),
+    /// the effects in the chain, in order
+    DspChanged(Vec<EffectInfo>),
+    /// shuffle or repeat were changed, by the gui or a script
+    ShuffleChanged(ShuffleMode),
+    RepeatChanged(RepeatMode),
+    /// the name of the output device in use, `None` while there is none
+    OutputChanged(Option<String>),
+    /// the port what's playing is served on, `None` while it isn't
+    StreamChanged(Option<u16>),
+    Error(String),
+}
+
+/// handle to the audio engine. the engine runs on its own thread and owns the output stream,
+/// the sink and the queue.
 pub struct PlayerManager {
-    pub sink: Sink,
-    _stream: OutputStream,
-    pub duration: Duration,
+    tx: Sender<PlayerMessage>,
+    /// for reporting an engine that stopped, it can't do that itself anymore
+    events_tx: UnboundedSender<PlayerEvent>,
+    events: Arc<Mutex<UnboundedReceiver<PlayerEvent>>>,
 }
 
 impl PlayerManager {
-    pub fn new() -> PlayerManager {
-        let (_stream, stream_handle) =
-            OutputStream::try_default().expect("could not create default OutputStream");
-        let sink = Sink::try_new(&stream_handle).expect("could not create new Sink");
+    pub fn new(config: &Config) -> PlayerManager {
+        let (tx, rx) = channel();
+        let (events_tx, events_rx) = unbounded_channel();
+        let config = config.clone();
+        let engine_events = events_tx.clone();
+        thread::Builder::new()
+            .name("player engine".to_string())
+            .spawn(move || {
+                let engine = || Engine::new(engine_events.clone(), &config).run(rx);
+                if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(engine)) {
+                    let reason = panic
+                        .downcast_ref::<&str>()
+                        .map(|s| s.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_default();
+                    let error = format!("the player engine crashed: {}", reason);
+                    let _ = engine_events.send(PlayerEvent::Error(error));
+                }
+            })
+            .expect("failed to spawn player engine");
         PlayerManager {
-            sink,
-            _stream,
-            duration: Duration::from_secs(1),
+            tx,
+            events_tx,
+            events: Arc::new(Mutex::new(events_rx)),
+        }
+    }
+
+    pub fn send(&self, message: PlayerMessage) {
+        if self.tx.send(message).is_err() {
+            println!("error: player engine is gone");
+            let error = "the player engine is gone, restart to play again".to_string();
+            let _ = self.events_tx.send(PlayerEvent::Error(error));
         }
     }
+
+    /// a sender for anything else that wants to drive the engine
+    pub fn sender(&self) -> Sender<PlayerMessage> {
+        self.tx.clone()
+    }
+
+    /// the events coming out of the engine
+    pub fn player_subscription(&self) -> Subscription<PlayerEvent> {
+        let events = self.events.clone();
+        Subscription::run_with_id(
+            "player events",
+            stream::channel(100, move |mut output| async move {
+                let mut events = events.lock().await;
+                while let Some(event) = events.recv().await {
+                    if output.send(event).await.is_err() {
+                        break;
+                    }
+                }
+            }),
+        )
+    }
 }
 
 impl Debug for PlayerManager {
@@ -31,3 +225,859 @@ impl Debug for PlayerManager {
     }
 }
 
+struct Engine {
+    sink: Sink,
+    /// what the sink plays on, `None` while there is no working output and the sink is idle
+    output: Option<Output>,
+    /// what kind of output to play on
+    output_kind: OutputKind,
+    /// name of the output device asked for, `None` for the default one
+    device: Option<String>,
+    /// the last position seen moving and when, to notice an output that stopped
+    progress: (Duration, Instant),
+    /// length of the current track, `None` while it isn't known
+    duration: Option<Duration>,
+    queue: Queue,
+    /// what the source in the sink is playing, shared with it
+    playback: Option<Arc<std::sync::Mutex<Playback>>>,
+    /// the last `Playback::transitions` seen, to notice when the source moved on
+    transitions: u64,
+    crossfade: Crossfade,
+    replay_gain: ReplayGainSettings,
+    eq: Option<EqPreset>,
+    /// effects after the equalizer, shared with every playback so they outlive it
+    dsp: Arc<std::sync::Mutex<DspChain>>,
+    /// fades the source around pausing and stopping, shared with every source
+    fader: Arc<Fader>,
+    volume: f32,
+    muted: bool,
+    speed: f32,
+    /// in semitones
+    pitch: f32,
+    /// seed for every shuffle, from the config, or new for each one if unset
+    shuffle_seed: Option<u64>,
+    /// where every source sends a copy of what it plays, for the stream
+    tap: Arc<TapSlot>,
+    stream: Option<Streamer>,
+    /// the port to stream on from the start
+    stream_port: Option<u16>,
+    /// whether we're supposed to be playing, as opposed to paused or stopped
+    playing: bool,
+    /// the last position sent out
+    reported_pos: Duration,
+    events: UnboundedSender<PlayerEvent>,
+}
+
+impl Engine {
+    fn new(events: UnboundedSender<PlayerEvent>, config: &Config) -> Engine {
+        let mut engine = Engine {
+            sink: Sink::new_idle().0,
+            output: None,
+            output_kind: config.output.clone(),
+            device: config.output_device.clone(),
+            progress: (Duration::ZERO, Instant::now()),
+            duration: None,
+            queue: Queue::default(),
+            playback: None,
+            transitions: 0,
+            crossfade: Crossfade::new(config),
+            replay_gain: ReplayGainSettings::new(config),
+            eq: config.equalizer(),
+            dsp: Arc::new(std::sync::Mutex::new(DspChain::new(&config.dsp_chain))),
+            fader: Arc::new(Fader::default()),
+            volume: config.volume,
+            muted: config.muted,
+            speed: config.speed,
+            pitch: config.pitch_semitones,
+            shuffle_seed: config.shuffle_seed,
+            tap: Arc::new(TapSlot::default()),
+            stream: None,
+            stream_port: config.stream_port,
+            playing: false,
+            reported_pos: Duration::ZERO,
+            events,
+        };
+        engine.set_shuffle(config.shuffle);
+        engine.queue.set_repeat(config.repeat);
+        engine
+    }
+
+    fn run(mut self, rx: Receiver<PlayerMessage>) {
+        self.open_output();
+        self.set_stream(self.stream_port);
+        self.dsp_changed();
+        loop {
+            match rx.recv_timeout(TICK) {
+                Ok(message) => self.handle(message),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            self.tick();
+        }
+    }
+
+    fn emit(&self, event: PlayerEvent) {
+        // the gui going away just means nobody is listening anymore
+        let _ = self.events.send(event);
+    }
+
+    fn handle(&mut self, message: PlayerMessage) {
+        match message {
+            PlayerMessage::Play => {
+                if self.output.is_none() {
+                    // the device might be back
+                    self.open_output();
+                    if self.output.is_none() {
+                        return;
+                    }
+                }
+                if self.sink.empty() {
+                    // nothing loaded, start the current entry again or the first one
+                    let index = self.queue.current_index().unwrap_or(0);
+                    self.play_entry(index);
+                } else {
+                    self.sink.play();
+                    self.fader.fade_in();
+                    self.set_playing(true);
+                }
+            }
+            PlayerMessage::Paus => {
+                self.fade_out();
+                self.sink.pause();
+                self.set_playing(false);
+                // where it stopped exactly, not where it was last reported
+                self.report_position(self.position());
+            }
+            PlayerMessage::Stop => {
+                self.fade_out();
+                self.sink.clear();
+                self.playback = None;
+                self.set_playing(false);
+                self.report_position(Duration::ZERO);
+            }
+            PlayerMessage::Next => {
+                if self.queue.skip().is_some() {
+                    self.start();
+                }
+            }
+            PlayerMessage::Prev => {
+                if self.position() > PREV_RESTARTS_AFTER {
+                    self.seek(Duration::ZERO);
+                } else if self.queue.prev().is_some() {
+                    self.start();
+                } else {
+                    self.seek(Duration::ZERO);
+                }
+            }
+            PlayerMessage::Seek(pos) => match self.duration {
+                Some(duration) => {
+                    self.seek(Duration::from_secs_f64(pos.get() * duration.as_secs_f64()))
+                }
+                None => self.emit(PlayerEvent::Error(
+                    "can't seek before the length of the track is known".to_string(),
+                )),
+            },
+            PlayerMessage::Enqueue(song) => {
+                self.queue.push(*song);
+                if self.sink.empty() {
+                    self.play_entry(self.queue.entries().len() - 1);
+                } else {
+                    self.queue_changed();
+                    self.preload();
+                }
+            }
+            PlayerMessage::Jump(index) => self.play_entry(index),
+            PlayerMessage::SetCrossfade(crossfade) => {
+                self.crossfade = crossfade;
+                self.preload();
+            }
+            PlayerMessage::SetReplayGain(replay_gain) => {
+                self.replay_gain = replay_gain;
+                if let Some(playback) = &self.playback {
+                    let mut playback = playback.lock().expect("playback lock poisoned");
+                    let open: Vec<usize> = playback
+                        .current()
+                        .map(|t| t.index)
+                        .into_iter()
+                        .chain(playback.next_index())
+                        .collect();
+                    for index in open {
+                        let gain = self.replay_gain.gain(&self.queue, index);
+                        playback.set_gain(index, gain);
+                    }
+                }
+            }
+            PlayerMessage::SetEqualizer(eq) => {
+                self.eq = eq.clone();
+                if let Some(playback) = &self.playback {
+                    playback
+                        .lock()
+                        .expect("playback lock poisoned")
+                        .eq_mut()
+                        .set(eq);
+                }
+            }
+            PlayerMessage::Dsp(command) => {
+                let result = self.dsp.lock().expect("dsp lock poisoned").apply(command);
+                if let Err(e) = result {
+                    self.emit(PlayerEvent::Error(e));
+                }
+                self.dsp_changed();
+            }
+            PlayerMessage::SetVolume(volume) => {
+                self.volume = volume.clamp(0.0, 1.0);
+                self.apply_volume();
+            }
+            PlayerMessage::SetMuted(muted) => {
+                self.muted = muted;
+                self.apply_volume();
+            }
+            PlayerMessage::SetSpeed(speed) => {
+                self.speed = speed;
+                self.apply_stretch();
+            }
+            PlayerMessage::SetPitch(pitch) => {
+                self.pitch = pitch;
+                self.apply_stretch();
+            }
+            PlayerMessage::SetShuffle(mode) => {
+                self.set_shuffle(mode);
+                self.queue_changed();
+                self.preload();
+                self.emit(PlayerEvent::ShuffleChanged(mode));
+            }
+            PlayerMessage::SetRepeat(mode) => {
+                self.queue.set_repeat(mode);
+                self.preload();
+                self.emit(PlayerEvent::RepeatChanged(mode));
+            }
+            PlayerMessage::SetOutputDevice(device) => {
+                self.output_kind = OutputKind::Device;
+                self.device = device;
+                self.switch_output();
+            }
+            PlayerMessage::SetOutput(kind) => {
+                self.output_kind = kind;
+                self.switch_output();
+            }
+            PlayerMessage::SetStream(port) => self.set_stream(port),
+            PlayerMessage::Restore(saved) => {
+                self.queue.restore(saved.entries, saved.current, saved.order);
+                self.load(saved.position, false);
+            }
+            PlayerMessage::SetDuration(path, duration) => {
+                if !self.queue.set_duration(&path, duration) {
+                    return;
+                }
+                self.queue_changed();
+                let current = self.queue.current_index().map(|i| &self.queue.entries()[i]);
+                if current.is_some_and(|song| song.path == path) {
+                    self.duration = Some(duration);
+                    self.emit(PlayerEvent::DurationChanged(duration));
+                }
+            }
+            PlayerMessage::FilesMoved(moved) => {
+                if self.queue.files_moved(&moved) {
+                    self.queue_changed();
+                }
+            }
+        }
+    }
+
+    /// move playback over to the device asked for, carrying on where it was. if the device
+    /// can't be opened the old one keeps playing.
+    fn switch_output(&mut self) {
+        let (output, sink) = match output::open(&self.output_kind, self.device.as_deref()) {
+            Ok(opened) => opened,
+            Err(e) => {
+                self.emit(PlayerEvent::Error(e));
+                return;
+            }
+        };
+        sink.set_volume(volume_gain(self.volume, self.muted));
+        if !self.playing {
+            sink.pause();
+        }
+        // the old source has to be gone before the new one starts, it hands back the part of
+        // its block it hadn't played
+        drop(std::mem::replace(&mut self.sink, sink));
+        self.output = None;
+        // the playback is shared, so a new source on the new sink picks up where the old
+        // one was
+        if let Some(playback) = &self.playback {
+            self.sink.append(self.source(playback));
+        }
+        self.emit(PlayerEvent::OutputChanged(Some(output.name.clone())));
+        self.output = Some(output);
+        self.progress = (self.position(), Instant::now());
+    }
+
+    /// open the configured output. a remembered device that isn't there is replaced by the
+    /// default one for now.
+    fn open_output(&mut self) {
+        self.switch_output();
+        if self.output.is_none() && self.output_kind == OutputKind::Device && self.device.is_some()
+        {
+            let device = self.device.take();
+            self.switch_output();
+            self.device = device;
+        }
+    }
+
+    /// a source for the sink playing `playback`, copied to the stream
+    fn source(&self, playback: &Arc<std::sync::Mutex<Playback>>) -> Tap<PlaybackSource> {
+        let source = PlaybackSource::new(playback.clone(), self.fader.clone());
+        Tap::new(source, self.tap.clone())
+    }
+
+    /// serve what's playing over http on `port`, or stop serving it
+    fn set_stream(&mut self, port: Option<u16>) {
+        if self.stream.as_ref().map(|s| s.port) == port {
+            return;
+        }
+        // the old stream has to let go of its port first
+        self.stream = None;
+        if let Some(port) = port {
+            match Streamer::start(port, self.tap.clone()) {
+                Ok(stream) => {
+                    self.stream = Some(stream);
+                    if let Some(index) = self.queue.current_index() {
+                        self.set_stream_title(&self.queue.entries()[index]);
+                    }
+                }
+                Err(e) => self.emit(PlayerEvent::Error(e)),
+            }
+        }
+        self.emit(PlayerEvent::StreamChanged(self.stream.as_ref().map(|s| s.port)));
+    }
+
+    fn set_stream_title(&self, song: &Song) {
+        if let Some(stream) = &self.stream {
+            stream.set_title(stream_title(song));
+        }
+    }
+
+    fn track_started(&self, song: Song) {
+        self.set_stream_title(&song);
+        self.emit(PlayerEvent::TrackStarted(Box::new(song), self.duration));
+    }
+
+    /// the output stopped taking samples, most likely because the device was unplugged
+    fn output_lost(&mut self) {
+        self.sink.pause();
+        self.fader.fade_out();
+        self.set_playing(false);
+        let error = match self.output.take() {
+            Some(output) if self.output_kind != OutputKind::Device => {
+                format!("output {} stopped, paused", output.name)
+            }
+            Some(output) if output_devices().contains(&output.name) => {
+                format!("output device {:?} stopped playing, paused", output.name)
+            }
+            Some(output) => format!("output device {:?} went away, paused", output.name),
+            None => "there is no output device to play on, paused".to_string(),
+        };
+        self.emit(PlayerEvent::Error(error));
+        self.emit(PlayerEvent::OutputChanged(None));
+    }
+
+    fn set_shuffle(&mut self, mode: ShuffleMode) {
+        let seed = self.shuffle_seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos() as u64
+        });
+        self.queue.set_shuffle(mode, seed);
+    }
+
+    fn apply_volume(&self) {
+        self.sink.set_volume(volume_gain(self.volume, self.muted));
+    }
+
+    fn stretcher(&self) -> Stretcher {
+        let mut stretcher = Stretcher::default();
+        stretcher.set(self.speed, self.pitch);
+        stretcher
+    }
+
+    fn apply_stretch(&self) {
+        if let Some(playback) = &self.playback {
+            let mut playback = playback.lock().expect("playback lock poisoned");
+            playback.stretch_mut().set(self.speed, self.pitch);
+        }
+    }
+
+    /// fade the source out and wait for it, so pausing or stopping doesn't click
+    fn fade_out(&self) {
+        self.fader.fade_out();
+        // a paused or empty sink isn't pulling samples, so the fade would never finish
+        if !self.playing || self.sink.is_paused() || self.sink.empty() {
+            return;
+        }
+        let deadline = Instant::now() + PAUSE_FADE * 4;
+        while !self.fader.is_silent() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(2));
+        }
+    }
+
+    /// check for the end of the track and keep the position up to date
+    fn tick(&mut self) {
+        // the source can move on to the preloaded track and play that to the end as well
+        // between two ticks, so the queue catches up before the end is handled
+        let state = self.playback.as_ref().map(|playback| {
+            let mut playback = playback.lock().expect("playback lock poisoned");
+            (playback.transitions(), playback.take_error())
+        });
+        if let Some((transitions, error)) = state {
+            if let Some(e) = error {
+                self.emit(PlayerEvent::Error(e));
+            }
+            if transitions != self.transitions {
+                self.transitions = transitions;
+                self.advanced();
+            }
+        }
+        if self.playing && self.sink.empty() {
+            self.emit(PlayerEvent::TrackEnded);
+            match self.queue.next() {
+                Some(_) => self.start(),
+                None => self.set_playing(false),
+            }
+            return;
+        }
+        if self.playback.is_none() {
+            return;
+        }
+        let pos = self.position();
+        if pos.abs_diff(self.reported_pos) >= POSITION_STEP {
+            self.report_position(pos);
+        }
+        if pos != self.progress.0 || !self.playing || self.sink.is_paused() {
+            self.progress = (pos, Instant::now());
+        } else if self.output.as_ref().is_some_and(Output::may_stall) {
+            self.progress.1 = Instant::now();
+        } else if self.progress.1.elapsed() > OUTPUT_STALL {
+            self.output_lost();
+        }
+    }
+
+    /// the source went on to the preloaded track by itself, catch the queue up with it
+    fn advanced(&mut self) {
+        let Some(playback) = &self.playback else {
+            return;
+        };
+        let Some(index) = playback.lock().expect("playback lock poisoned").moved_to() else {
+            return;
+        };
+        let Some(song) = self.queue.jump(index).cloned() else {
+            return;
+        };
+        // the track may have been played to the end already
+        let duration = self.with_current(|t| t.duration()).flatten();
+        self.duration = song_duration(&song, duration);
+        self.emit(PlayerEvent::TrackEnded);
+        self.track_started(song);
+        self.queue_changed();
+        self.report_position(self.position());
+        self.preload();
+    }
+
+    fn with_current<T>(&self, f: impl FnOnce(&Track) -> T) -> Option<T> {
+        let playback = self.playback.as_ref()?;
+        let playback = playback.lock().expect("playback lock poisoned");
+        playback.current().map(f)
+    }
+
+    /// how far into the current track we are
+    fn position(&self) -> Duration {
+        self.with_current(|t| t.position()).unwrap_or_default()
+    }
+
+    fn report_position(&mut self, pos: Duration) {
+        self.reported_pos = pos;
+        self.emit(PlayerEvent::PositionChanged(pos));
+    }
+
+    fn set_playing(&mut self, playing: bool) {
+        if self.playing != playing {
+            self.playing = playing;
+            self.emit(PlayerEvent::PlayingChanged(playing));
+        }
+    }
+
+    fn dsp_changed(&self) {
+        let effects = self.dsp.lock().expect("dsp lock poisoned").describe();
+        self.emit(PlayerEvent::DspChanged(effects));
+    }
+
+    fn queue_changed(&self) {
+        self.emit(PlayerEvent::QueueChanged(
+            self.queue.entries().to_vec(),
+            self.queue.current_index(),
+            self.queue.order().to_vec(),
+        ));
+    }
+
+    fn seek(&mut self, pos: Duration) {
+        let result = match (&self.output, &self.playback) {
+            // nothing takes samples from the sink, so it would wait for the seek forever
+            (None, Some(playback)) => playback.lock().expect("playback lock poisoned").seek(pos),
+            _ => self.sink.try_seek(pos),
+        };
+        match result {
+            Ok(()) => self.report_position(pos),
+            Err(e) => self.emit(PlayerEvent::Error(format!(
+                "could not seek: {}",
+                seek_error(e)
+            ))),
+        }
+    }
+
+    /// make `index` the current queue entry and play it
+    fn play_entry(&mut self, index: usize) {
+        if self.queue.jump(index).is_some() {
+            self.start();
+        }
+    }
+
+    /// replace whatever is playing with the current queue entry
+    fn start(&mut self) {
+        self.load(Duration::ZERO, true);
+    }
+
+    /// replace whatever is playing with the current queue entry, starting at `position`.
+    /// without `play` it waits paused for a `Play`.
+    fn load(&mut self, mut position: Duration, play: bool) {
+        // whatever is playing goes quiet first, so switching tracks doesn't click
+        self.fade_out();
+        self.sink.clear();
+        self.playback = None;
+        self.queue_changed();
+        let Some(index) = self.queue.current_index() else {
+            return;
+        };
+        let song = self.queue.entries()[index].clone();
+        let gain = self.replay_gain.gain(&self.queue, index);
+        let track = match Track::open(index, &song.path, gain) {
+            Ok(track) => track,
+            Err(e) => {
+                self.emit(PlayerEvent::Error(format!(
+                    "failed to play {:?}: {}",
+                    song.path, e
+                )));
+                self.set_playing(false);
+                return;
+            }
+        };
+        self.duration = song_duration(&song, track.duration());
+        let mut playback = Playback::new(
+            track,
+            self.stretcher(),
+            Equalizer::new(self.eq.clone()),
+            self.dsp.clone(),
+        );
+        if !position.is_zero() {
+            if let Err(e) = playback.seek(position) {
+                self.emit(PlayerEvent::Error(format!(
+                    "could not seek: {}",
+                    seek_error(e)
+                )));
+                position = Duration::ZERO;
+            }
+        }
+        let playback = Arc::new(std::sync::Mutex::new(playback));
+        // a new source starts silent, and stays that way until the fader opens
+        if play {
+            self.fader.fade_in();
+        } else {
+            self.fader.fade_out();
+        }
+        self.sink.append(self.source(&playback));
+        self.playback = Some(playback);
+        self.transitions = 0;
+        if play {
+            self.sink.play();
+        } else {
+            self.sink.pause();
+        }
+        self.report_position(position);
+        if play {
+            self.track_started(song);
+        } else {
+            self.set_stream_title(&song);
+            self.emit(PlayerEvent::TrackLoaded(Box::new(song), self.duration));
+        }
+        self.set_playing(play);
+        self.preload();
+    }
+
+    /// open the entry after the current one ahead of time, so the source can carry on with
+    /// it without a gap, or fade over into it
+    fn preload(&mut self) {
+        let Some(playback) = self.playback.clone() else {
+            return;
+        };
+        let (Some(current), Some(index)) = (self.queue.current_index(), self.queue.peek_next())
+        else {
+            // whatever was lined up isn't next anymore
+            playback
+                .lock()
+                .expect("playback lock poisoned")
+                .clear_next();
+            return;
+        };
+        let entries = self.queue.entries();
+        let fade = self.crossfade.between(&entries[current], &entries[index]);
+        {
+            let mut playback = playback.lock().expect("playback lock poisoned");
+            playback.set_fade(fade, self.crossfade.curve);
+            if playback.next_index() == Some(index) {
+                return;
+            }
+        }
+        // opening happens outside the lock so the audio thread never waits on the disk
+        let path = &self.queue.entries()[index].path;
+        let gain = self.replay_gain.gain(&self.queue, index);
+        match Track::open(index, path, gain) {
+            Ok(track) => playback
+                .lock()
+                .expect("playback lock poisoned")
+                .set_next(track),
+            // it gets reported properly if it still fails when its turn comes
+            Err(e) => println!("error: failed to preload {:?}: {}", path, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path, process, thread::JoinHandle};
+
+    use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+
+    use super::*;
+    use crate::eq::{EqBand, EqMode, FilterKind};
+
+    /// an engine on its own thread like [`PlayerManager::new`] starts, without a sound card
+    /// and with its events coming out of a plain receiver instead of an iced subscription
+    fn spawn_engine(
+        output: OutputKind,
+    ) -> (
+        Sender<PlayerMessage>,
+        UnboundedReceiver<PlayerEvent>,
+        JoinHandle<()>,
+    ) {
+        let config = Config {
+            output,
+            ..Config::default()
+        };
+        let (tx, rx) = channel();
+        let (events_tx, events) = unbounded_channel();
+        let engine = thread::spawn(move || Engine::new(events_tx, &config).run(rx));
+        (tx, events, engine)
+    }
+
+    /// wait for the first event `matches` likes, failing after a while
+    fn wait_for(
+        events: &mut UnboundedReceiver<PlayerEvent>,
+        matches: impl Fn(&PlayerEvent) -> bool,
+    ) -> PlayerEvent {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Instant::now() < deadline {
+            match events.try_recv() {
+                Ok(event) if matches(&event) => return event,
+                Ok(_) => {}
+                Err(_) => thread::sleep(Duration::from_millis(5)),
+            }
+        }
+        panic!("the engine never got there");
+    }
+
+    /// wait for the next track to start and return its path
+    fn started(events: &mut UnboundedReceiver<PlayerEvent>) -> PathBuf {
+        match wait_for(events, |e| matches!(e, PlayerEvent::TrackStarted(..))) {
+            PlayerEvent::TrackStarted(song, _) => song.path,
+            _ => unreachable!(),
+        }
+    }
+
+    /// an empty directory of its own for a test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("thump-{}-{}", name, process::id()));
+        fs::create_dir_all(&dir).expect("failed to create test dir");
+        dir
+    }
+
+    /// a 44.1 kHz stereo float WAV of `frames` frames, with `frame(i)` as frame `i`
+    fn write_wav(path: &Path, frames: usize, frame: impl Fn(usize) -> [f32; 2]) {
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: 44100,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        };
+        let mut writer = WavWriter::create(path, spec).expect("failed to create test wav");
+        for sample in (0..frames).flat_map(frame) {
+            writer
+                .write_sample(sample)
+                .expect("failed to write test wav");
+        }
+        writer.finalize().expect("failed to write test wav");
+    }
+
+    /// the queue of `tracks`, loaded paused at the start of the first
+    fn restore(tx: &Sender<PlayerMessage>, tracks: &[PathBuf]) {
+        let saved = SavedQueue {
+            entries: tracks.iter().cloned().map(Song::new).collect(),
+            current: Some(0),
+            order: (0..tracks.len()).collect(),
+            position: Duration::ZERO,
+        };
+        tx.send(PlayerMessage::Restore(Box::new(saved))).unwrap();
+    }
+
+    /// play `tracks` into a WAV in `dir` and return what was heard, without the silence from
+    /// before play was pressed and after the queue ran out. `setup` is sent once the first
+    /// track is loaded.
+    fn record(dir: &Path, tracks: &[PathBuf], setup: Vec<PlayerMessage>) -> Vec<[f32; 2]> {
+        let out = dir.join("out.wav");
+        let (tx, mut events, engine) = spawn_engine(OutputKind::Wav {
+            path: out.clone(),
+            realtime: false,
+        });
+        // loaded paused, so the second track is lined up before anything plays
+        restore(&tx, tracks);
+        wait_for(&mut events, |e| matches!(e, PlayerEvent::TrackLoaded(..)));
+        for message in setup {
+            tx.send(message).unwrap();
+        }
+        tx.send(PlayerMessage::Play).unwrap();
+        wait_for(&mut events, |e| matches!(e, PlayerEvent::PlayingChanged(false)));
+        // the wav file is finished once the engine and its output are gone
+        drop(tx);
+        engine.join().expect("engine panicked");
+
+        let samples: Vec<f32> = WavReader::open(&out)
+            .expect("failed to open output")
+            .into_samples()
+            .collect::<Result<_, _>>()
+            .expect("failed to read output");
+        let frames: Vec<[f32; 2]> = samples.chunks(2).map(|f| [f[0], f[1]]).collect();
+        let start = frames.iter().position(|f| f[1] != 0.0).expect("nothing played");
+        let end = frames.iter().rposition(|f| f[1] != 0.0).expect("nothing played") + 1;
+        frames[start..end].to_vec()
+    }
+
+    #[test]
+    fn plays_tracks_back_to_back() {
+        let dir = test_dir("gapless");
+        let (first, second) = (dir.join("a.wav"), dir.join("b.wav"));
+        let (first_frames, second_frames) = (22050, 33075);
+        write_wav(&first, first_frames, |_| [0.5, 0.25]);
+        write_wav(&second, second_frames, |_| [-0.5, -0.25]);
+        let played = record(&dir, &[first, second], Vec::new());
+        fs::remove_dir_all(&dir).expect("failed to remove test dir");
+        assert_eq!(played.len(), first_frames + second_frames);
+
+        // the first one fades in, after that both play as they are right up to the seam
+        let gain = played[first_frames - 1][0] / 0.5;
+        assert!(played[..first_frames]
+            .iter()
+            .all(|f| f[1] > 0.0 && (f[0] - 2.0 * f[1]).abs() < 1e-6));
+        assert_eq!(played[first_frames - 1], [0.5 * gain, 0.25 * gain]);
+        assert_eq!(played[first_frames], [-0.5 * gain, -0.25 * gain]);
+        assert!(played[first_frames..]
+            .iter()
+            .all(|f| f == &[-0.5 * gain, -0.25 * gain]));
+    }
+
+    #[test]
+    fn seeks_to_the_frame() {
+        let dir = test_dir("seek");
+        let track = dir.join("numbered.wav");
+        // the left channel counts the frames, against the right so the fade in cancels out
+        write_wav(&track, 44100, |i| [i as f32 / 100_000.0, 0.5]);
+        let duration = Duration::from_secs(1);
+        let seek = PlayerMessage::Seek(SeekPos::from_secs_percent(0.25, duration));
+        let played = record(&dir, &[track], vec![seek]);
+        fs::remove_dir_all(&dir).expect("failed to remove test dir");
+
+        let frame = |f: &[f32; 2]| (f[0] / f[1] * 50_000.0).round() as usize;
+        assert_eq!(frame(&played[0]), 11025);
+        assert_eq!(played.len(), 44100 - 11025);
+        assert_eq!(frame(&played[played.len() - 1]), 44099);
+    }
+
+    #[test]
+    fn skips_and_repeats_to_the_right_track() {
+        let dir = test_dir("skip");
+        let tracks: Vec<PathBuf> = ["a.wav", "b.wav", "c.wav"]
+            .iter()
+            .map(|name| dir.join(name))
+            .collect();
+        for track in &tracks {
+            write_wav(track, 22050, |_| [0.1, 0.1]);
+        }
+        // at the pace of a sound card, so nothing ends while the next message is on its way
+        let (tx, mut events, engine) = spawn_engine(OutputKind::Null { realtime: true });
+        restore(&tx, &tracks);
+        wait_for(&mut events, |e| matches!(e, PlayerEvent::TrackLoaded(..)));
+
+        tx.send(PlayerMessage::Next).unwrap();
+        assert_eq!(started(&mut events), tracks[1]);
+        tx.send(PlayerMessage::Prev).unwrap();
+        assert_eq!(started(&mut events), tracks[0]);
+        // the end of a track starts it again, skipping still moves on
+        tx.send(PlayerMessage::SetRepeat(RepeatMode::One)).unwrap();
+        assert_eq!(started(&mut events), tracks[0]);
+        tx.send(PlayerMessage::Next).unwrap();
+        assert_eq!(started(&mut events), tracks[1]);
+
+        drop(tx);
+        engine.join().expect("engine panicked");
+        fs::remove_dir_all(&dir).expect("failed to remove test dir");
+    }
+
+    #[test]
+    fn effects_are_heard() {
+        let dir = test_dir("effects");
+        let track = dir.join("a.wav");
+        write_wav(&track, 44100, |_| [0.2, 0.1]);
+        let tracks = [track];
+        // the level the track ends at, once the fade in and the filters have settled
+        let level = |setup| record(&dir, &tracks, setup).last().copied().unwrap();
+        let plain = level(Vec::new());
+
+        // a low shelf sets the level of the constant signal
+        let eq = EqPreset {
+            name: "shelf".to_string(),
+            preamp: 0.0,
+            mode: EqMode::Parametric {
+                bands: vec![EqBand {
+                    kind: FilterKind::LowShelf,
+                    freq: 1000.0,
+                    gain: 6.0,
+                    q: 0.707,
+                }],
+            },
+        };
+        let boosted = level(vec![PlayerMessage::SetEqualizer(Some(eq))]);
+        // well below the threshold, so the compressor only adds its makeup gain
+        let compressor = vec![
+            PlayerMessage::Dsp(DspCommand::Add("compressor".to_string())),
+            PlayerMessage::Dsp(DspCommand::SetParam(0, "threshold".to_string(), 0.0)),
+            PlayerMessage::Dsp(DspCommand::SetParam(0, "makeup".to_string(), 6.0)),
+        ];
+        let compressed = level(compressor);
+        fs::remove_dir_all(&dir).expect("failed to remove test dir");
+
+        let db = |a: [f32; 2], b: [f32; 2]| [0, 1].map(|c| 20.0 * (a[c] / b[c]).log10());
+        for gain in db(boosted, plain) {
+            assert!((gain - 6.0).abs() < 0.01, "{}", gain);
+        }
+        for gain in db(compressed, plain) {
+            assert!((gain - 6.0).abs() < 0.01, "{}", gain);
+        }
+    }
+}
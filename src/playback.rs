@@ -0,0 +1,529 @@
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use rodio::{source::SeekError, Source};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config, decode::SampleReader, dsp::DspChain, eq::Equalizer, stretch::Stretcher, Song,
+};
+
+/// longest crossfade that can be set
+pub const MAX_CROSSFADE: Duration = Duration::from_secs(12);
+/// how long the fades around pausing, resuming and stopping take
+pub const PAUSE_FADE: Duration = Duration::from_millis(30);
+
+/// how the volume moves during a crossfade
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FadeCurve {
+    Linear,
+    /// keeps the combined loudness steady, the usual choice for music
+    EqualPower,
+    /// slow at both ends, quick in the middle
+    SCurve,
+}
+
+impl FadeCurve {
+    /// gain of the track fading in, `t` going from 0 to 1 over the fade.
+    /// the track fading out gets `gain(1 - t)`.
+    fn gain(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            FadeCurve::Linear => t,
+            FadeCurve::EqualPower => (t * std::f32::consts::FRAC_PI_2).sin(),
+            FadeCurve::SCurve => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+/// crossfade settings, see the `crossfade_*` fields of [`Config`]
+#[derive(Debug, Clone, Copy)]
+pub struct Crossfade {
+    pub length: Duration,
+    pub curve: FadeCurve,
+    pub same_album: bool,
+}
+
+impl Crossfade {
+    pub fn new(config: &Config) -> Crossfade {
+        Crossfade {
+            length: Duration::from_secs_f32(config.crossfade_secs.max(0.0)).min(MAX_CROSSFADE),
+            curve: config.crossfade_curve,
+            same_album: config.crossfade_same_album,
+        }
+    }
+
+    /// how long `from` and `to` should overlap. zero between tracks of one album unless
+    /// that is turned on, so albums stay gapless.
+    pub fn between(&self, from: &Song, to: &Song) -> Duration {
+        if from.same_album(to) && !self.same_album {
+            Duration::ZERO
+        } else {
+            self.length
+        }
+    }
+}
+
+/// a queue entry opened for playback
+pub struct Track {
+    /// index of the queue entry
+    pub index: usize,
+    reader: SampleReader,
+    /// decoded samples that weren't handed out yet. the first block is decoded when the
+    /// track is opened, so switching to it doesn't wait on the disk.
+    pending: Vec<f32>,
+    /// frames handed to the output so far
+    played: u64,
+    /// factor the samples are scaled by, for ReplayGain
+    gain: f32,
+}
+
+impl Track {
+    /// open the file and decode its first block
+    pub fn open(index: usize, path: &Path, gain: f32) -> Result<Track, String> {
+        let mut reader = SampleReader::open(path).map_err(|e| e.to_string())?;
+        let pending = reader
+            .next_block()
+            .map_err(|e| e.to_string())?
+            .map(|block| block.to_vec())
+            .unwrap_or_default();
+        Ok(Track {
+            index,
+            reader,
+            pending,
+            played: 0,
+            gain,
+        })
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        self.reader.duration()
+    }
+
+    pub fn position(&self) -> Duration {
+        Duration::from_secs_f64(self.played as f64 / self.reader.sample_rate() as f64)
+    }
+
+    fn seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        let actual = self
+            .reader
+            .seek(pos)
+            .map_err(|e| SeekError::Other(Box::new(e)))?;
+        self.pending.clear();
+        self.played = (actual.as_secs_f64() * self.reader.sample_rate() as f64).round() as u64;
+        Ok(())
+    }
+
+    /// frames left after what was handed out, if the length is known
+    fn remaining(&self) -> Option<u64> {
+        Some(self.reader.frames()?.saturating_sub(self.played))
+    }
+
+    fn same_format(&self, other: &Track) -> bool {
+        self.reader.sample_rate() == other.reader.sample_rate()
+            && self.reader.channels() == other.reader.channels()
+    }
+
+    /// replace `block` with the next samples. `false` at the end of the track.
+    fn fill(&mut self, block: &mut Vec<f32>) -> Result<bool, String> {
+        if self.pending.is_empty() {
+            match self.reader.next_block() {
+                Ok(Some(samples)) => self.pending.extend_from_slice(samples),
+                Ok(None) => return Ok(false),
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        std::mem::swap(block, &mut self.pending);
+        self.pending.clear();
+        self.played += (block.len() / self.reader.channels()) as u64;
+        self.apply_gain(block);
+        Ok(true)
+    }
+
+    /// put up to `frames` frames into `out`, fewer if the track ends first
+    fn take(&mut self, frames: usize, out: &mut Vec<f32>) {
+        let channels = self.reader.channels();
+        out.clear();
+        while out.len() < frames * channels {
+            if self.pending.is_empty() {
+                match self.reader.next_block() {
+                    Ok(Some(samples)) => self.pending.extend_from_slice(samples),
+                    // errors come up again once this track is the current one
+                    _ => break,
+                }
+            }
+            let n = (frames * channels - out.len()).min(self.pending.len());
+            out.extend(self.pending.drain(..n));
+        }
+        self.played += (out.len() / channels) as u64;
+        self.apply_gain(out);
+    }
+
+    fn apply_gain(&self, samples: &mut [f32]) {
+        if self.gain != 1.0 {
+            samples.iter_mut().for_each(|s| *s *= self.gain);
+        }
+    }
+}
+
+/// what the engine and the [`PlaybackSource`] share: the track that is playing and the one
+/// lined up after it. when the current track runs out the source carries on with the next
+/// one in the same stream, so there is no gap between them. with a crossfade set the next
+/// track is mixed in over the end of the current one.
+pub struct Playback {
+    current: Option<Track>,
+    next: Option<Track>,
+    /// how long the current and the next track overlap
+    fade: Duration,
+    curve: FadeCurve,
+    /// samples of the next track being mixed in
+    mix: Vec<f32>,
+    /// speed and pitch, ahead of the equalizer so it works on what is heard
+    stretch: Stretcher,
+    eq: Equalizer,
+    dsp: Arc<Mutex<DspChain>>,
+    /// how many times the source moved on to the next track
+    transitions: u64,
+    /// queue index of the track it last moved on to, still known once that one is done too
+    moved_to: Option<usize>,
+    /// the last decode error, for the engine to report
+    error: Option<String>,
+    /// what a source that went away hadn't played yet of its last block, with its sample
+    /// rate and channel count. the next source starts with it.
+    leftover: Option<(Vec<f32>, u32, u16)>,
+}
+
+impl Playback {
+    pub fn new(
+        track: Track,
+        stretch: Stretcher,
+        eq: Equalizer,
+        dsp: Arc<Mutex<DspChain>>,
+    ) -> Playback {
+        Playback {
+            current: Some(track),
+            next: None,
+            fade: Duration::ZERO,
+            curve: FadeCurve::EqualPower,
+            mix: Vec::new(),
+            stretch,
+            eq,
+            dsp,
+            transitions: 0,
+            moved_to: None,
+            error: None,
+            leftover: None,
+        }
+    }
+
+    pub fn current(&self) -> Option<&Track> {
+        self.current.as_ref()
+    }
+
+    /// queue index of the preloaded track
+    pub fn next_index(&self) -> Option<usize> {
+        self.next.as_ref().map(|t| t.index)
+    }
+
+    /// line up the track to play after the current one
+    pub fn set_next(&mut self, track: Track) {
+        self.next = Some(track);
+    }
+
+    pub fn clear_next(&mut self) {
+        self.next = None;
+    }
+
+    /// change the gain of the track for queue entry `index`, if it is open
+    pub fn set_gain(&mut self, index: usize, gain: f32) {
+        for track in [&mut self.current, &mut self.next].into_iter().flatten() {
+            if track.index == index {
+                track.gain = gain;
+            }
+        }
+    }
+
+    /// overlap the current and the next track by `length`, zero for none
+    pub fn set_fade(&mut self, length: Duration, curve: FadeCurve) {
+        self.fade = length;
+        self.curve = curve;
+    }
+
+    pub fn stretch_mut(&mut self) -> &mut Stretcher {
+        &mut self.stretch
+    }
+
+    pub fn eq_mut(&mut self) -> &mut Equalizer {
+        &mut self.eq
+    }
+
+    pub fn transitions(&self) -> u64 {
+        self.transitions
+    }
+
+    pub fn moved_to(&self) -> Option<usize> {
+        self.moved_to
+    }
+
+    pub fn take_error(&mut self) -> Option<String> {
+        self.error.take()
+    }
+
+    /// seek the current track. sources seek through this, the engine only calls it
+    /// directly while no source is being played.
+    pub fn seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        if let Some(track) = self.current.as_mut() {
+            track.seek(pos)?;
+        }
+        // a fade that already started is undone, it starts over when we get there again
+        if let Some(next) = self.next.as_mut().filter(|t| t.played > 0) {
+            next.seek(Duration::ZERO)?;
+        }
+        self.stretch.reset();
+        self.leftover = None;
+        Ok(())
+    }
+
+    /// the next block of the stream with its sample rate and channel count, moving on to
+    /// the next track when the current one is done. `None` once there is nothing left.
+    fn fill(&mut self, block: &mut Vec<f32>) -> Option<(u32, u16)> {
+        if let Some((samples, sample_rate, channels)) = self.leftover.take() {
+            *block = samples;
+            return Some((sample_rate, channels));
+        }
+        loop {
+            let track = self.current.as_mut()?;
+            match track.fill(block) {
+                Ok(true) => {
+                    let (sample_rate, channels) =
+                        (track.reader.sample_rate(), track.reader.channels());
+                    self.mix_next(block);
+                    self.stretch.process(block, sample_rate, channels);
+                    if block.is_empty() {
+                        // the stretcher is still taking in audio
+                        continue;
+                    }
+                    self.eq.process(block, sample_rate, channels);
+                    self.dsp.lock().expect("dsp lock poisoned").process(
+                        block,
+                        sample_rate,
+                        channels,
+                    );
+                    return Some((sample_rate, channels as u16));
+                }
+                Ok(false) => {}
+                Err(e) => self.error = Some(format!("decoding failed: {}", e)),
+            }
+            self.current = self.next.take();
+            if let Some(track) = &self.current {
+                self.transitions += 1;
+                self.moved_to = Some(track.index);
+            }
+        }
+    }
+
+    /// mix the start of the next track into the part of `block` that falls into the fade.
+    /// tracks with a different sample rate or channel count, or of unknown length, just
+    /// follow each other.
+    fn mix_next(&mut self, block: &mut [f32]) {
+        let (Some(current), Some(next)) = (&self.current, &mut self.next) else {
+            return;
+        };
+        let Some(remaining) = current.remaining() else {
+            return;
+        };
+        if self.fade.is_zero() || !current.same_format(next) {
+            return;
+        }
+        let channels = current.reader.channels();
+        let fade = (self.fade.as_secs_f64() * current.reader.sample_rate() as f64) as u64;
+        let frames = block.len() / channels;
+        // frame i of the block has `remaining + frames - i` frames left, counting itself
+        let first = (remaining + frames as u64).saturating_sub(fade) as usize;
+        if first >= frames {
+            return;
+        }
+        next.take(frames - first, &mut self.mix);
+        for (i, incoming) in self.mix.chunks(channels).enumerate() {
+            let left = remaining + (frames - first - i) as u64;
+            let t = 1.0 - left as f32 / fade as f32;
+            let (gain_out, gain_in) = (self.curve.gain(1.0 - t), self.curve.gain(t));
+            let start = (first + i) * channels;
+            for (sample, incoming) in block[start..start + channels].iter_mut().zip(incoming) {
+                *sample = *sample * gain_out + incoming * gain_in;
+            }
+        }
+    }
+}
+
+/// lets the engine fade the source out before pausing or stopping, and back in after.
+/// once faded out the source holds its place and plays silence, so nothing is skipped.
+#[derive(Debug, Default)]
+pub struct Fader {
+    /// whether the source should be at full level
+    open: AtomicBool,
+    /// set by the source while it's faded all the way out
+    silent: AtomicBool,
+}
+
+impl Fader {
+    pub fn fade_in(&self) {
+        self.open.store(true, Ordering::Relaxed);
+    }
+
+    pub fn fade_out(&self) {
+        self.open.store(false, Ordering::Relaxed);
+    }
+
+    /// whether a fade out has finished
+    pub fn is_silent(&self) -> bool {
+        self.silent.load(Ordering::Relaxed)
+    }
+}
+
+/// the one source appended to the sink, playing whatever the [`Playback`] has
+pub struct PlaybackSource {
+    playback: Arc<Mutex<Playback>>,
+    block: Vec<f32>,
+    pos: usize,
+    sample_rate: u32,
+    channels: u16,
+    fader: Arc<Fader>,
+    /// where the fade is at, 0 to 1
+    level: f32,
+    /// zeros still owed for a frame of silence being held. silence is always handed out in
+    /// whole frames so the channels stay in line, however it's pulled.
+    held: usize,
+    /// mono zeros still to go before the first block, see [`LEAD_IN`]
+    lead_in: usize,
+}
+
+/// a sink with nothing to play has rodio fill in mono silence, and has told its output
+/// to expect 512 samples of it before asking about the format again. a source appended in
+/// between gets read as mono for what's left of those, so a new source starts with just as
+/// much mono silence to get past them with its channels in line.
+const LEAD_IN: usize = 512;
+
+impl PlaybackSource {
+    /// a new source starts silent and fades in once `fader` is open
+    pub fn new(playback: Arc<Mutex<Playback>>, fader: Arc<Fader>) -> PlaybackSource {
+        let mut source = PlaybackSource {
+            playback,
+            block: Vec::new(),
+            pos: 0,
+            sample_rate: 44100,
+            channels: 2,
+            fader,
+            level: 0.0,
+            held: 0,
+            lead_in: LEAD_IN,
+        };
+        source.refill();
+        source
+    }
+
+    /// move the fade on by a frame. `false` means the source is faded out and should hold.
+    fn step_fade(&mut self) -> bool {
+        let open = self.fader.open.load(Ordering::Relaxed);
+        let step = 1.0 / (PAUSE_FADE.as_secs_f32() * self.sample_rate as f32);
+        self.level = match open {
+            true => (self.level + step).min(1.0),
+            false => (self.level - step).max(0.0),
+        };
+        let silent = !open && self.level == 0.0;
+        self.fader.silent.store(silent, Ordering::Relaxed);
+        !silent
+    }
+
+    fn refill(&mut self) {
+        self.pos = 0;
+        let mut playback = self.playback.lock().expect("playback lock poisoned");
+        match playback.fill(&mut self.block) {
+            Some((sample_rate, channels)) => {
+                self.sample_rate = sample_rate;
+                self.channels = channels;
+            }
+            None => self.block.clear(),
+        }
+    }
+}
+
+impl Drop for PlaybackSource {
+    fn drop(&mut self) {
+        // a source goes away with the output it played on. what it hadn't played yet goes
+        // back, so the source on the next output carries on from right here.
+        if self.pos < self.block.len() {
+            let rest = self.block.split_off(self.pos);
+            if let Ok(mut playback) = self.playback.lock() {
+                playback.leftover = Some((rest, self.sample_rate, self.channels));
+            }
+        }
+    }
+}
+
+impl Iterator for PlaybackSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.lead_in > 0 {
+            self.lead_in -= 1;
+            return Some(0.0);
+        }
+        if self.held > 0 {
+            self.held -= 1;
+            return Some(0.0);
+        }
+        let sample = *self.block.get(self.pos)?;
+        // the fade only moves, and only holds, between frames so channels stay in line
+        if self.pos.is_multiple_of(self.channels as usize) && !self.step_fade() {
+            self.held = self.channels as usize - 1;
+            return Some(0.0);
+        }
+        self.pos += 1;
+        // refill right away so current_frame_len always knows about format changes
+        if self.pos == self.block.len() {
+            self.refill();
+        }
+        Some(sample * self.level)
+    }
+}
+
+impl Source for PlaybackSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        match self.lead_in {
+            0 => Some(self.held + self.block.len() - self.pos),
+            lead_in => Some(lead_in),
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        match self.lead_in {
+            0 => self.channels,
+            _ => 1,
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.playback
+            .lock()
+            .expect("playback lock poisoned")
+            .seek(pos)?;
+        self.refill();
+        Ok(())
+    }
+}
@@ -0,0 +1,391 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Song;
+
+/// the order the queue is played in
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShuffleMode {
+    /// the order the entries were added in
+    #[default]
+    Off,
+    /// every entry once, in random order
+    Track,
+    /// albums in random order, the tracks of each album in order
+    Album,
+    /// like `Track`, but rarely played and highly rated entries tend to come sooner
+    Weighted,
+}
+
+impl ShuffleMode {
+    pub fn parse(value: &str) -> Option<ShuffleMode> {
+        match value {
+            "off" => Some(ShuffleMode::Off),
+            "track" => Some(ShuffleMode::Track),
+            "album" => Some(ShuffleMode::Album),
+            "weighted" => Some(ShuffleMode::Weighted),
+            _ => None,
+        }
+    }
+}
+
+/// what happens when playback gets to the end of a track
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RepeatMode {
+    /// stop at the end of the queue
+    #[default]
+    Off,
+    /// play the current track over and over
+    One,
+    /// start the queue over at the end
+    All,
+    /// start the album of the current track over at its end
+    Album,
+}
+
+impl RepeatMode {
+    pub fn parse(value: &str) -> Option<RepeatMode> {
+        match value {
+            "off" => Some(RepeatMode::Off),
+            "one" => Some(RepeatMode::One),
+            "all" => Some(RepeatMode::All),
+            "album" => Some(RepeatMode::Album),
+            _ => None,
+        }
+    }
+}
+
+/// small seedable random number generator (splitmix64), so a shuffle can be played back
+/// exactly from its seed
+#[derive(Debug, Default)]
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// uniform in `0.0..1.0`
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// uniform in `0..n`
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_f64() * n as f64) as usize
+    }
+
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.below(i + 1));
+        }
+    }
+}
+
+/// how likely an entry is to come up early in a weighted shuffle
+fn weight(song: &Song) -> f64 {
+    let rating = song.rating.unwrap_or(0.5) as f64;
+    (0.5 + rating) / (1.0 + song.play_count as f64).sqrt()
+}
+
+/// the songs lined up to play, which one is playing, and how we got to it
+#[derive(Debug, Default)]
+pub struct Queue {
+    entries: Vec<Song>,
+    current: Option<usize>,
+    /// entries that were current before, most recent last. `prev` walks back through these
+    history: Vec<usize>,
+    shuffle: ShuffleMode,
+    repeat: RepeatMode,
+    /// indices into `entries` in the order they are played
+    order: Vec<usize>,
+    rng: Rng,
+}
+
+impl Queue {
+    pub fn push(&mut self, song: Song) {
+        self.entries.push(song);
+        let index = self.entries.len() - 1;
+        if self.shuffle == ShuffleMode::Off {
+            self.order.push(index);
+        } else {
+            // somewhere among the entries that haven't played yet
+            let after = self.order_pos().map_or(0, |p| p + 1);
+            let at = after + self.rng.below(self.order.len() - after + 1);
+            self.order.insert(at, index);
+        }
+    }
+
+    pub fn entries(&self) -> &[Song] {
+        &self.entries
+    }
+
+    pub fn current_index(&self) -> Option<usize> {
+        self.current
+    }
+
+    /// indices into [`Queue::entries`] in the order they are played
+    pub fn order(&self) -> &[usize] {
+        &self.order
+    }
+
+    /// point the entries at the files in `moved` to where they were moved to. `false` if
+    /// none of them were queued.
+    pub fn files_moved(&mut self, moved: &HashMap<PathBuf, PathBuf>) -> bool {
+        let mut found = false;
+        for song in &mut self.entries {
+            if let Some(to) = moved.get(&song.path) {
+                song.path = to.clone();
+                found = true;
+            }
+        }
+        found
+    }
+
+    /// set the length of the entries playing the file at `path`. `false` if there are none.
+    pub fn set_duration(&mut self, path: &Path, duration: Duration) -> bool {
+        let mut found = false;
+        for song in self.entries.iter_mut().filter(|s| s.path == path) {
+            song.duration = Some(duration);
+            found = true;
+        }
+        found
+    }
+
+    /// the entries played right before and right after `index`
+    pub fn neighbours(&self, index: usize) -> (Option<&Song>, Option<&Song>) {
+        let Some(pos) = self.order.iter().position(|&i| i == index) else {
+            return (None, None);
+        };
+        let entry = |pos: usize| self.order.get(pos).map(|&i| &self.entries[i]);
+        (pos.checked_sub(1).and_then(entry), entry(pos + 1))
+    }
+
+    /// switch the shuffle mode. the current entry stays where it is and plays on, the rest
+    /// is lined up after it. turning shuffle off goes back to the order the entries were
+    /// added in, carrying on after the current one. the same seed gives the same order.
+    pub fn set_shuffle(&mut self, mode: ShuffleMode, seed: u64) {
+        self.shuffle = mode;
+        self.rng = Rng(seed);
+        let mut order: Vec<usize> = (0..self.entries.len()).collect();
+        match mode {
+            ShuffleMode::Off => {}
+            ShuffleMode::Track => self.rng.shuffle(&mut order),
+            ShuffleMode::Album => {
+                let mut albums: Vec<Vec<usize>> = Vec::new();
+                for index in order {
+                    let song = &self.entries[index];
+                    match albums
+                        .iter_mut()
+                        .find(|album| song.same_album(&self.entries[album[0]]))
+                    {
+                        Some(album) => album.push(index),
+                        None => albums.push(vec![index]),
+                    }
+                }
+                for album in &mut albums {
+                    album.sort_by_key(|&i| {
+                        let song = &self.entries[i];
+                        (song.disc_number, song.track_number)
+                    });
+                }
+                self.rng.shuffle(&mut albums);
+                // the album that is playing goes first so it carries on from the current
+                // track, the tracks before that come around again at the very end
+                if let Some(current) = self.current {
+                    if let Some(pos) = albums.iter().position(|a| a.contains(&current)) {
+                        let mut album = albums.remove(pos);
+                        let at = album.iter().position(|&i| i == current).unwrap_or(0);
+                        let before: Vec<usize> = album.drain(..at).collect();
+                        albums.insert(0, album);
+                        albums.push(before);
+                    }
+                }
+                order = albums.concat();
+            }
+            ShuffleMode::Weighted => {
+                // weighted sampling without replacement: sort by u^(1/w)
+                let mut keyed: Vec<(f64, usize)> = order
+                    .into_iter()
+                    .map(|i| (self.rng.next_f64().powf(1.0 / weight(&self.entries[i])), i))
+                    .collect();
+                keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+                order = keyed.into_iter().map(|(_, i)| i).collect();
+            }
+        }
+        if let (Some(current), ShuffleMode::Track | ShuffleMode::Weighted) = (self.current, mode) {
+            order.retain(|&i| i != current);
+            order.insert(0, current);
+        }
+        self.order = order;
+    }
+
+    pub fn set_repeat(&mut self, mode: RepeatMode) {
+        self.repeat = mode;
+    }
+
+    /// where the current entry is in the play order
+    fn order_pos(&self) -> Option<usize> {
+        let current = self.current?;
+        self.order.iter().position(|&i| i == current)
+    }
+
+    /// put back entries saved earlier, in the order they were played in. an order that
+    /// doesn't fit the entries is replaced by the one they were added in.
+    pub fn restore(&mut self, entries: Vec<Song>, current: Option<usize>, order: Vec<usize>) {
+        let mut sorted = order.clone();
+        sorted.sort_unstable();
+        self.order = if sorted.iter().copied().eq(0..entries.len()) {
+            order
+        } else {
+            (0..entries.len()).collect()
+        };
+        self.current = current.filter(|&c| c < entries.len());
+        self.entries = entries;
+        self.history.clear();
+    }
+
+    /// make `index` the current entry, remembering the old one for `prev`
+    pub fn jump(&mut self, index: usize) -> Option<&Song> {
+        if index >= self.entries.len() {
+            return None;
+        }
+        if let Some(current) = self.current.filter(|c| *c != index) {
+            self.history.push(current);
+        }
+        self.current = Some(index);
+        self.entries.get(index)
+    }
+
+    /// the entry `next` would move to, without moving
+    pub fn peek_next(&self) -> Option<usize> {
+        match self.repeat {
+            RepeatMode::One => self.current.or(self.order.first().copied()),
+            RepeatMode::Album => self.next_on_album(),
+            RepeatMode::Off | RepeatMode::All => self.after_current(),
+        }
+    }
+
+    /// the entry after the current one in play order, wrapping around with repeat on
+    fn after_current(&self) -> Option<usize> {
+        let next = self.order_pos().map_or(0, |p| p + 1);
+        match self.order.get(next) {
+            Some(&next) => Some(next),
+            None if self.repeat != RepeatMode::Off => self.order.first().copied(),
+            None => None,
+        }
+    }
+
+    /// the next track of the current album in play order, going back to its first one at
+    /// the end. a track without an album is an album of its own.
+    fn next_on_album(&self) -> Option<usize> {
+        let Some(current) = self.current else {
+            return self.order.first().copied();
+        };
+        let song = &self.entries[current];
+        let pos = self.order_pos().unwrap_or(0);
+        let on_album = |&&i: &&usize| song.same_album(&self.entries[i]);
+        self.order[pos + 1..]
+            .iter()
+            .find(on_album)
+            .or_else(|| self.order.iter().find(on_album))
+            .copied()
+            .or(Some(current))
+    }
+
+    /// move on to the entry that plays after the current one when it ends. at the end of the
+    /// queue, without repeat, nothing changes and `None` is returned.
+    pub fn next(&mut self) -> Option<&Song> {
+        let next = self.peek_next()?;
+        self.jump(next)
+    }
+
+    /// move on to the next entry because the user asked to. repeating a single track
+    /// doesn't keep them on it.
+    pub fn skip(&mut self) -> Option<&Song> {
+        let next = match self.repeat {
+            RepeatMode::One => self.after_current()?,
+            _ => self.peek_next()?,
+        };
+        self.jump(next)
+    }
+
+    /// go back to the entry that was playing before the current one
+    pub fn prev(&mut self) -> Option<&Song> {
+        let prev = match self.history.pop() {
+            Some(prev) => prev,
+            None => self.order[self.order_pos()?.checked_sub(1)?],
+        };
+        self.current = Some(prev);
+        self.entries.get(prev)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// two albums of three tracks, added with their tracks mixed up, and a single
+    fn queue() -> Queue {
+        let song = |album: Option<&str>, track: i32, rating: f32, play_count: u32| {
+            let mut song = Song::new(PathBuf::from(format!("{:?} {}", album, track)));
+            song.album_name = album.map(str::to_string);
+            song.track_number = Some(track);
+            song.rating = Some(rating);
+            song.play_count = play_count;
+            song
+        };
+        let mut queue = Queue::default();
+        for song in [
+            song(Some("a"), 2, 0.5, 0),
+            song(Some("b"), 1, 1.0, 0),
+            song(Some("a"), 1, 0.2, 10),
+            song(None, 1, 0.9, 1),
+            song(Some("b"), 3, 0.0, 40),
+            song(Some("a"), 3, 0.6, 2),
+            song(Some("b"), 2, 0.7, 3),
+        ] {
+            queue.push(song);
+        }
+        queue
+    }
+
+    #[test]
+    fn shuffle_is_reproducible_from_seed() {
+        for (mode, order) in [
+            (ShuffleMode::Off, [0, 1, 2, 3, 4, 5, 6]),
+            (ShuffleMode::Track, [2, 3, 6, 4, 1, 0, 5]),
+            (ShuffleMode::Album, [1, 6, 4, 2, 0, 5, 3]),
+            (ShuffleMode::Weighted, [5, 0, 3, 1, 6, 2, 4]),
+        ] {
+            let mut queue = queue();
+            queue.set_shuffle(mode, 42);
+            assert_eq!(queue.order(), order, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn shuffle_keeps_current_entry() {
+        for mode in [
+            ShuffleMode::Track,
+            ShuffleMode::Album,
+            ShuffleMode::Weighted,
+        ] {
+            let mut queue = queue();
+            queue.jump(5);
+            queue.set_shuffle(mode, 7);
+            assert_eq!(queue.order()[0], 5, "{:?}", mode);
+        }
+    }
+}
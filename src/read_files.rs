@@ -1,40 +1,278 @@
-use std::{fs, path::Path};
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
 
-use lofty::{file::TaggedFileExt, read_from_path, tag::TagItem};
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Match,
+};
+use lofty::{
+    file::TaggedFileExt,
+    read_from_path,
+    tag::{ItemValue, TagItem},
+};
 
-use crate::Song;
+use crate::{
+    config::Config,
+    duration,
+    replaygain::{parse_gain, parse_peak},
+    Song,
+};
+
+/// gitignore style file that can be dropped in any directory of the library
+const IGNORE_FILE: &str = ".thumpignore";
+
+/// which files a scan hands to lofty
+#[derive(Debug, Clone)]
+pub struct ScanRules {
+    /// lowercase extensions of files worth parsing
+    extensions: HashSet<String>,
+    /// gitignore style patterns from the config, relative to the scan root
+    exclude: Vec<String>,
+    /// how many directories deep to go below the root, `None` for no limit
+    max_depth: Option<usize>,
+    /// don't cross into other mounted file systems
+    one_file_system: bool,
+    follow_symlinks: bool,
+}
+
+impl ScanRules {
+    pub fn new(config: &Config) -> ScanRules {
+        ScanRules {
+            extensions: config
+                .audio_extensions
+                .iter()
+                .map(|e| e.trim_start_matches('.').to_lowercase())
+                .collect(),
+            exclude: config.exclude.clone(),
+            max_depth: config.max_scan_depth,
+            one_file_system: config.one_file_system,
+            follow_symlinks: config.follow_symlinks,
+        }
+    }
+
+    fn is_audio(&self, path: &Path) -> bool {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| self.extensions.contains(&e.to_lowercase()))
+    }
+}
 
 /// recursivly search a directory for sound files, parse, and return them.
-pub fn search_dir<T: AsRef<Path>>(path: T) -> Vec<Song> {
+pub fn search_dir<T: AsRef<Path>>(path: T, rules: &ScanRules) -> Vec<Song> {
     println!("searching {:?}", path.as_ref());
-    let songs = fs::read_dir(path).expect("failed to read dir");
-    let songs = songs
-        .map(|song| {
-            let song = song.expect("song is error");
-            if song.file_type().expect("failed to get file type").is_dir() {
-                search_dir(song.path())
-            } else {
-                let path = song.path();
-                let tagged_file = match read_from_path(path.clone()) {
-                    Ok(tf) => tf,
-                    Err(_) => {
-                        println!("error: cannot read tagged file {:?}", path);
-                        return Vec::new();
-                    }
-                };
-                let a = match tagged_file.primary_tag() {
-                    Some(tf) => tf,
-                    None => {
-                        println!("error: no primary tag {:?}", path);
-                        return Vec::new();
-                    }
-                };
-                vec![a.items().fold(Song::new(path.clone()), fold_songs)]
+    list_files(path, rules)
+        .into_iter()
+        .filter_map(read_song)
+        .collect()
+}
+
+/// recursivly list every sound file under `path`, skipping anything excluded by the config
+/// or a `.thumpignore` on the way down. every directory is only entered once, however many
+/// symlinks or bind mounts lead to it.
+pub fn list_files<T: AsRef<Path>>(path: T, rules: &ScanRules) -> Vec<PathBuf> {
+    let root = path.as_ref();
+    let mut builder = GitignoreBuilder::new(root);
+    for pattern in &rules.exclude {
+        if let Err(e) = builder.add_line(None, pattern) {
+            println!("error: bad exclude pattern {:?}: {}", pattern, e);
+        }
+    }
+    let global = builder.build().unwrap_or_else(|e| {
+        println!("error: failed to build exclude patterns: {}", e);
+        Gitignore::empty()
+    });
+    let root_meta = match fs::metadata(root) {
+        Ok(meta) => meta,
+        Err(e) => {
+            println!("error: cannot scan {:?}: {}", root, e);
+            return Vec::new();
+        }
+    };
+
+    let mut walker = Walker {
+        rules,
+        ignores: vec![global],
+        visited: HashSet::from([dir_id(root, &root_meta)]),
+        root_device: device(&root_meta),
+        files: Vec::new(),
+    };
+    walker.walk(root, 0);
+    walker.files
+}
+
+/// identifies a directory no matter which path it was reached through
+#[cfg(unix)]
+type DirId = (u64, u64);
+#[cfg(not(unix))]
+type DirId = PathBuf;
+
+#[cfg(unix)]
+fn dir_id(_path: &Path, meta: &fs::Metadata) -> DirId {
+    use std::os::unix::fs::MetadataExt;
+    (meta.dev(), meta.ino())
+}
+
+#[cfg(not(unix))]
+fn dir_id(path: &Path, _meta: &fs::Metadata) -> DirId {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(unix)]
+fn device(meta: &fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(meta.dev())
+}
+
+#[cfg(not(unix))]
+fn device(_meta: &fs::Metadata) -> Option<u64> {
+    None
+}
+
+/// state for a single scan of one root
+struct Walker<'a> {
+    rules: &'a ScanRules,
+    /// the global excludes followed by every `.thumpignore` between the root and the
+    /// current directory, the deepest one last so it gets the final say
+    ignores: Vec<Gitignore>,
+    visited: HashSet<DirId>,
+    /// device of the root, to stay on it when `one_file_system` is set
+    root_device: Option<u64>,
+    files: Vec<PathBuf>,
+}
+
+impl Walker<'_> {
+    fn walk(&mut self, dir: &Path, depth: usize) {
+        let ignore_file = dir.join(IGNORE_FILE);
+        let has_ignore_file = ignore_file.is_file();
+        if has_ignore_file {
+            let (ignore, error) = Gitignore::new(&ignore_file);
+            if let Some(e) = error {
+                println!("error: problem in {:?}: {}", ignore_file, e);
+            }
+            self.ignores.push(ignore);
+        }
+
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                println!("error: cannot read dir {:?}: {}", dir, e);
+                return;
+            }
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_symlink() && !self.rules.follow_symlinks {
+                continue;
             }
-        })
-        .flatten()
-        .collect();
-    songs
+            // follows symlinks, so a link to a directory is treated as one
+            let Ok(meta) = fs::metadata(&path) else {
+                println!("error: broken link or unreadable file {:?}", path);
+                continue;
+            };
+            if is_ignored(&self.ignores, &path, meta.is_dir()) {
+                continue;
+            }
+            if meta.is_dir() {
+                if self.should_enter(&path, &meta, depth + 1) {
+                    self.walk(&path, depth + 1);
+                }
+            } else if self.rules.is_audio(&path) {
+                self.files.push(path);
+            }
+        }
+
+        if has_ignore_file {
+            self.ignores.pop();
+        }
+    }
+
+    fn should_enter(&mut self, dir: &Path, meta: &fs::Metadata, depth: usize) -> bool {
+        if self.rules.max_depth.is_some_and(|max| depth > max) {
+            return false;
+        }
+        if self.rules.one_file_system && device(meta) != self.root_device {
+            return false;
+        }
+        if !self.visited.insert(dir_id(dir, meta)) {
+            println!("skipping {:?}, already scanned through another path", dir);
+            return false;
+        }
+        true
+    }
+}
+
+fn is_ignored(ignores: &[Gitignore], path: &Path, is_dir: bool) -> bool {
+    for ignore in ignores.iter().rev() {
+        match ignore.matched(path, is_dir) {
+            Match::Ignore(_) => return true,
+            Match::Whitelist(_) => return false,
+            Match::None => {}
+        }
+    }
+    false
+}
+
+/// parse the tags of a single sound file. prints an error and returns `None` if the file
+/// can't be read or has no tags.
+pub fn read_song(path: PathBuf) -> Option<Song> {
+    let tagged_file = match read_from_path(path.clone()) {
+        Ok(tf) => tf,
+        Err(_) => {
+            println!("error: cannot read tagged file {:?}", path);
+            return None;
+        }
+    };
+    let a = match tagged_file.primary_tag() {
+        Some(tf) => tf,
+        None => {
+            println!("error: no primary tag {:?}", path);
+            return None;
+        }
+    };
+    let mut song = a.items().fold(Song::new(path.clone()), fold_songs);
+    song.duration = duration::probe(&path, &tagged_file);
+    Some(song)
+}
+
+/// like [`read_song`] but quiet, and a sound file without tags gives a `Song` with only the
+/// path set. for places where fixing the tags is the point, like the inbox.
+pub fn read_song_untagged(path: PathBuf) -> Option<Song> {
+    let tagged_file = read_from_path(path.clone()).ok()?;
+    let duration = duration::probe(&path, &tagged_file);
+    let mut song = match tagged_file.primary_tag() {
+        Some(tag) => tag.items().fold(Song::new(path), fold_songs),
+        None => Song::new(path),
+    };
+    song.duration = duration;
+    Some(song)
+}
+
+/// a rating from 0 to 1. id3 keeps the raw POPM frame (email, nul, rating out of 255, play
+/// counter), other formats a number on a scale that depends on the tagger.
+fn parse_rating(value: &ItemValue) -> Option<f32> {
+    let rating = match value {
+        ItemValue::Binary(popm) => {
+            let email_end = popm.iter().position(|b| *b == 0)?;
+            *popm.get(email_end + 1)? as f32 / 255.0
+        }
+        ItemValue::Text(text) => {
+            let rating: f32 = text.trim().parse().ok()?;
+            match rating {
+                r if r <= 1.0 => r,
+                r if r <= 5.0 => r / 5.0,
+                r if r <= 100.0 => r / 100.0,
+                r => r / 255.0,
+            }
+        }
+        ItemValue::Locator(_) => return None,
+    };
+    (rating.is_finite() && rating > 0.0).then(|| rating.min(1.0))
 }
 
 /// takes a `Song` and a `TagItem` addes the metadata in the TagItem to the song or just
@@ -93,15 +331,19 @@ fn fold_songs(mut song: Song, tag: &TagItem) -> Song {
                 .into_string()
                 .map(|x| x.parse().expect("failed to parse value"))
         }
-        lofty::tag::ItemKey::DiscTotal => {}
+        lofty::tag::ItemKey::DiscTotal => {
+            song.disc_total = tag.into_value().into_string().and_then(|x| x.parse().ok())
+        }
         lofty::tag::ItemKey::TrackNumber => {
             song.track_number = tag
                 .into_value()
                 .into_string()
                 .map(|x| x.parse().expect("failed to parse value"))
         }
-        lofty::tag::ItemKey::TrackTotal => {}
-        lofty::tag::ItemKey::Popularimeter => {}
+        lofty::tag::ItemKey::TrackTotal => {
+            song.track_total = tag.into_value().into_string().and_then(|x| x.parse().ok())
+        }
+        lofty::tag::ItemKey::Popularimeter => song.rating = parse_rating(tag.value()),
         lofty::tag::ItemKey::ParentalAdvisory => {}
         lofty::tag::ItemKey::RecordingDate => song.recording_date = tag.into_value().into_string(),
         lofty::tag::ItemKey::Year => {}
@@ -133,10 +375,22 @@ fn fold_songs(mut song: Song, tag: &TagItem) -> Song {
         lofty::tag::ItemKey::EncoderSoftware => {}
         lofty::tag::ItemKey::EncoderSettings => {}
         lofty::tag::ItemKey::EncodingTime => {}
-        lofty::tag::ItemKey::ReplayGainAlbumGain => {}
-        lofty::tag::ItemKey::ReplayGainAlbumPeak => {}
-        lofty::tag::ItemKey::ReplayGainTrackGain => {}
-        lofty::tag::ItemKey::ReplayGainTrackPeak => {}
+        lofty::tag::ItemKey::ReplayGainAlbumGain => {
+            song.replay_gain.album_gain =
+                tag.into_value().into_string().and_then(|x| parse_gain(&x))
+        }
+        lofty::tag::ItemKey::ReplayGainAlbumPeak => {
+            song.replay_gain.album_peak =
+                tag.into_value().into_string().and_then(|x| parse_peak(&x))
+        }
+        lofty::tag::ItemKey::ReplayGainTrackGain => {
+            song.replay_gain.track_gain =
+                tag.into_value().into_string().and_then(|x| parse_gain(&x))
+        }
+        lofty::tag::ItemKey::ReplayGainTrackPeak => {
+            song.replay_gain.track_peak =
+                tag.into_value().into_string().and_then(|x| parse_peak(&x))
+        }
         lofty::tag::ItemKey::AudioFileUrl => {}
         lofty::tag::ItemKey::AudioSourceUrl => {}
         lofty::tag::ItemKey::CommercialInformationUrl => {}
@@ -0,0 +1,161 @@
+use std::path::Path;
+
+use lofty::{
+    config::WriteOptions,
+    file::{AudioFile, TaggedFileExt},
+    read_from_path,
+    tag::{ItemKey, Tag},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, queue::Queue};
+
+/// the ReplayGain values from a song's tags. gains are in dB, peaks are linear with 1.0
+/// being full scale.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ReplayGain {
+    pub track_gain: Option<f32>,
+    pub track_peak: Option<f32>,
+    pub album_gain: Option<f32>,
+    pub album_peak: Option<f32>,
+}
+
+/// parse a gain tag like `-6.48 dB`
+pub fn parse_gain(value: &str) -> Option<f32> {
+    let value = value.trim();
+    let value = value
+        .strip_suffix("dB")
+        .or_else(|| value.strip_suffix("db"))
+        .unwrap_or(value);
+    value.trim().parse().ok().filter(|g: &f32| g.is_finite())
+}
+
+/// parse a peak tag like `0.988831`
+pub fn parse_peak(value: &str) -> Option<f32> {
+    value
+        .trim()
+        .parse()
+        .ok()
+        .filter(|p: &f32| p.is_finite() && *p > 0.0)
+}
+
+/// write `gain` into the ReplayGain tags of the file at `path`. values that are `None`
+/// leave the tag as it is.
+pub fn write_tags(path: &Path, gain: &ReplayGain) -> lofty::error::Result<()> {
+    let mut tagged_file = read_from_path(path)?;
+    if tagged_file.primary_tag().is_none() {
+        tagged_file.insert_tag(Tag::new(tagged_file.primary_tag_type()));
+    }
+    let tag = tagged_file
+        .primary_tag_mut()
+        .expect("primary tag was just inserted");
+
+    let gains = [
+        (ItemKey::ReplayGainTrackGain, gain.track_gain),
+        (ItemKey::ReplayGainAlbumGain, gain.album_gain),
+    ];
+    for (key, value) in gains {
+        if let Some(value) = value {
+            tag.insert_text(key, format!("{:.2} dB", value));
+        }
+    }
+    let peaks = [
+        (ItemKey::ReplayGainTrackPeak, gain.track_peak),
+        (ItemKey::ReplayGainAlbumPeak, gain.album_peak),
+    ];
+    for (key, value) in peaks {
+        if let Some(value) = value {
+            tag.insert_text(key, format!("{:.6}", value));
+        }
+    }
+
+    tagged_file.save_to_path(path, WriteOptions::default())
+}
+
+/// which of the gains is used
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplayGainMode {
+    Off,
+    Track,
+    Album,
+    /// album gain while an album is played in order, track gain otherwise
+    Smart,
+}
+
+impl ReplayGainMode {
+    pub fn parse(value: &str) -> Option<ReplayGainMode> {
+        match value {
+            "off" => Some(ReplayGainMode::Off),
+            "track" => Some(ReplayGainMode::Track),
+            "album" => Some(ReplayGainMode::Album),
+            "smart" => Some(ReplayGainMode::Smart),
+            _ => None,
+        }
+    }
+}
+
+/// ReplayGain settings, see the `replay_gain*` fields of [`Config`]
+#[derive(Debug, Clone, Copy)]
+pub struct ReplayGainSettings {
+    pub mode: ReplayGainMode,
+    pub preamp: f32,
+    pub prevent_clipping: bool,
+}
+
+impl ReplayGainSettings {
+    pub fn new(config: &Config) -> ReplayGainSettings {
+        ReplayGainSettings {
+            mode: config.replay_gain,
+            preamp: config.replay_gain_preamp,
+            prevent_clipping: config.replay_gain_prevent_clipping,
+        }
+    }
+
+    /// the factor to scale the samples of queue entry `index` by. songs without tags use the
+    /// results of the loudness job, songs without either play as they are, with the preamp
+    /// left out as well.
+    pub fn gain(&self, queue: &Queue, index: usize) -> f32 {
+        let song = &queue.entries()[index];
+        let tags = match song.loudness {
+            Some(loudness)
+                if song.replay_gain.track_gain.is_none()
+                    && song.replay_gain.album_gain.is_none() =>
+            {
+                loudness.replay_gain()
+            }
+            _ => song.replay_gain,
+        };
+        let album = match self.mode {
+            ReplayGainMode::Off => return 1.0,
+            ReplayGainMode::Track => false,
+            ReplayGainMode::Album => true,
+            ReplayGainMode::Smart => {
+                // the tracks played around it being the ones of the album around it means
+                // the album is being played through
+                let (before, after) = queue.neighbours(index);
+                before.is_some_and(|before| song.follows(before))
+                    || after.is_some_and(|after| after.follows(song))
+            }
+        };
+        let (gain, peak) = if album {
+            (
+                tags.album_gain.or(tags.track_gain),
+                tags.album_peak.or(tags.track_peak),
+            )
+        } else {
+            (
+                tags.track_gain.or(tags.album_gain),
+                tags.track_peak.or(tags.album_peak),
+            )
+        };
+        let Some(gain) = gain else {
+            return 1.0;
+        };
+        let factor = 10f32.powf((gain + self.preamp) / 20.0);
+        match peak {
+            Some(peak) if self.prevent_clipping => factor.min(1.0 / peak),
+            _ => factor,
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::{
+    sync::mpsc::{Receiver, Sender},
+    time::Duration,
+};
+
+use rhai::Engine;
+
+use crate::{
+    config::Config,
+    dsp::DspCommand,
+    play_manager::PlayerMessage,
+    playback::{Crossfade, MAX_CROSSFADE},
+    queue::{RepeatMode, ShuffleMode},
+    replaygain::{ReplayGainMode, ReplayGainSettings},
+};
+
+/// start the rhai script on its own task. `get` and `put` talk to the gui through `rx` and
+/// `tx`, everything else drives the player engine through `player`.
+pub fn spawn(
+    rx: Receiver<String>,
+    tx: Sender<String>,
+    player: Sender<PlayerMessage>,
+    config: &Config,
+) {
+    let crossfade = Crossfade::new(config);
+    let replay_gain = ReplayGainSettings::new(config);
+    tokio::spawn(async move {
+        let mut engine = Engine::new();
+        let send =
+            move |message: PlayerMessage| player.send(message).expect("player engine is gone");
+        // a script function without arguments that sends `message`
+        let command = |message: PlayerMessage| {
+            let send = send.clone();
+            move || send(message.clone())
+        };
+        engine
+            .register_fn("get", move || rx.recv().unwrap_or_default())
+            .register_fn("put", move |v: String| tx.send(v).unwrap())
+            .register_fn("play", command(PlayerMessage::Play))
+            .register_fn("pause", command(PlayerMessage::Paus))
+            .register_fn("stop", command(PlayerMessage::Stop))
+            .register_fn("next", command(PlayerMessage::Next))
+            .register_fn("prev", command(PlayerMessage::Prev));
+        let send_crossfade = send.clone();
+        engine.register_fn("crossfade", move |secs: f64| {
+            let length = Duration::from_secs_f64(secs.max(0.0)).min(MAX_CROSSFADE);
+            send_crossfade(PlayerMessage::SetCrossfade(Crossfade {
+                length,
+                ..crossfade
+            }))
+        });
+        let send_replay_gain = send.clone();
+        engine.register_fn(
+            "replay_gain",
+            move |mode: &str| match ReplayGainMode::parse(mode) {
+                Some(mode) => send_replay_gain(PlayerMessage::SetReplayGain(ReplayGainSettings {
+                    mode,
+                    ..replay_gain
+                })),
+                None => println!("error: unknown replay gain mode {:?}", mode),
+            },
+        );
+        let send_shuffle = send.clone();
+        engine.register_fn("shuffle", move |mode: &str| {
+            match ShuffleMode::parse(mode) {
+                Some(mode) => send_shuffle(PlayerMessage::SetShuffle(mode)),
+                None => println!("error: unknown shuffle mode {:?}", mode),
+            }
+        });
+        let send_repeat = send.clone();
+        engine.register_fn("repeat", move |mode: &str| match RepeatMode::parse(mode) {
+            Some(mode) => send_repeat(PlayerMessage::SetRepeat(mode)),
+            None => println!("error: unknown repeat mode {:?}", mode),
+        });
+        let send_dsp = send.clone();
+        engine.register_fn("add_effect", move |name: &str| {
+            send_dsp(PlayerMessage::Dsp(DspCommand::Add(name.to_string())))
+        });
+        let send_dsp = send.clone();
+        engine.register_fn("remove_effect", move |index: i64| {
+            send_dsp(PlayerMessage::Dsp(DspCommand::Remove(index as usize)))
+        });
+        let send_dsp = send.clone();
+        engine.register_fn(
+            "set_effect_param",
+            move |index: i64, name: &str, value: f64| {
+                send_dsp(PlayerMessage::Dsp(DspCommand::SetParam(
+                    index as usize,
+                    name.to_string(),
+                    value as f32,
+                )))
+            },
+        );
+
+        engine
+            .run(
+                r#"
+        print("from script");
+        loop {
+            let value = get();
+            // print(`got ${value}`);
+        }
+        "#,
+            )
+            .expect("failed to run script");
+    });
+}
@@ -7,7 +7,7 @@ use iced::{
     Color, Element, Point, Rectangle, Size,
 };
 
-use crate::{Message, PlayerMessage};
+use crate::{play_manager::PlayerMessage, Message};
 
 /// universal way of representing a seekers position
 #[derive(Debug, Clone, Copy)]
@@ -0,0 +1,51 @@
+use std::{fs, path::PathBuf, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, Song, View};
+
+/// the queue as it was left, for the engine to carry on with
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SavedQueue {
+    pub entries: Vec<Song>,
+    pub current: Option<usize>,
+    /// indices into `entries` in the order they are played
+    pub order: Vec<usize>,
+    /// how far into the current entry playback was
+    pub position: Duration,
+}
+
+/// what was going on when thump was closed, so the next start can pick up from there.
+/// volume, shuffle and repeat are in the config already.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    pub queue: SavedQueue,
+    pub view: View,
+}
+
+fn path() -> PathBuf {
+    Config::dir().join("session.json")
+}
+
+/// the session saved last, an empty one if there is none
+pub fn load() -> Session {
+    let path = path();
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(_) => return Session::default(),
+    };
+    serde_json::from_str(&contents).unwrap_or_else(|e| {
+        println!("error: failed to parse session {:?}: {}", path, e);
+        Session::default()
+    })
+}
+
+pub fn save(session: &Session) {
+    let path = path();
+    let contents = serde_json::to_string(session).expect("failed to serialize session");
+    if let Err(e) = fs::create_dir_all(Config::dir()).and_then(|_| fs::write(&path, contents)) {
+        println!("error: failed to save session to {:?}: {}", path, e);
+    }
+}
@@ -0,0 +1,229 @@
+use std::path::{Path, PathBuf};
+
+use iced::futures::Stream;
+use rustfft::{num_complex::Complex, FftPlanner};
+use serde::{Deserialize, Serialize};
+
+use crate::{analysis, decode::SampleReader};
+
+/// size of each fft window in samples
+const FFT_SIZE: usize = 4096;
+/// only look at the first part of a track, the cutoff doesn't move around
+const MAX_SECONDS: usize = 90;
+/// windows quieter than this (rms) are skipped so silence doesn't drag the average down
+const SILENCE_RMS: f32 = 1e-4;
+/// how far above the noise floor a bin has to be to count as content
+const CONTENT_ABOVE_FLOOR_DB: f32 = 15.0;
+/// share of the bins, the quietest ones, the noise floor is taken from
+const FLOOR_SHARE: f32 = 0.01;
+/// drop across the cutoff needed to call it a brick wall lowpass
+const SHARP_DROP_DB: f32 = 30.0;
+/// cutoffs above this are too close to what real masters do to say anything
+const MAX_LOSSY_CUTOFF_HZ: f32 = 20_500.0;
+
+/// extensions of containers that should only ever hold lossless audio.
+/// a lossy cutoff in one of these means the file was transcoded.
+const LOSSLESS_EXTENSIONS: [&str; 6] = ["flac", "wav", "aiff", "aif", "wv", "ape"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Verdict {
+    /// the spectrum reaches (close to) nyquist
+    Clean,
+    /// sharp lowpass well below nyquist, typical for a lossy encoder
+    Lossy,
+    /// soft roll-off or not enough signal to tell
+    Inconclusive,
+}
+
+/// result of running [`analyze`] on a file
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SpectrumAnalysis {
+    /// highest frequency with real content
+    pub cutoff_hz: f32,
+    pub verdict: Verdict,
+}
+
+impl SpectrumAnalysis {
+    /// best guess at what the file was encoded from, based on common encoder lowpass settings
+    pub fn likely_source(&self) -> &'static str {
+        match self.verdict {
+            Verdict::Clean => "lossless",
+            Verdict::Inconclusive => "unknown",
+            Verdict::Lossy => match self.cutoff_hz {
+                c if c < 15_000.0 => "low bitrate lossy (< 128 kbps)",
+                c if c < 16_500.0 => "~128 kbps lossy",
+                c if c < 18_000.0 => "~160 kbps lossy",
+                c if c < 19_500.0 => "~192 kbps lossy",
+                _ => "256-320 kbps lossy",
+            },
+        }
+    }
+}
+
+/// whether the file's container claims to be lossless, and so is worth analyzing
+pub fn is_lossless_container<T: AsRef<Path>>(path: T) -> bool {
+    path.as_ref()
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|ext| LOSSLESS_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// decode `path`, average its spectrum and estimate the effective frequency cutoff.
+pub fn analyze<T: AsRef<Path>>(path: T) -> Result<SpectrumAnalysis, String> {
+    let mut reader =
+        SampleReader::open(path.as_ref()).map_err(|e| format!("failed to open: {}", e))?;
+    let sample_rate = reader.sample_rate();
+    let max_samples = sample_rate as usize * MAX_SECONDS;
+
+    let fft = FftPlanner::<f32>::new().plan_fft_forward(FFT_SIZE);
+    let window: Vec<f32> = (0..FFT_SIZE)
+        .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / (FFT_SIZE - 1) as f32).cos())
+        .collect();
+    let mut power = vec![0.0f64; FFT_SIZE / 2];
+    let mut windows = 0;
+    let mut mono = Vec::with_capacity(FFT_SIZE);
+    let mut buffer = vec![Complex::new(0.0, 0.0); FFT_SIZE];
+    let mut consumed = 0;
+
+    while consumed < max_samples {
+        let channels = reader.channels();
+        let block = match reader.next_block() {
+            Ok(Some(block)) => block,
+            Ok(None) => break,
+            Err(e) => return Err(format!("failed to decode: {}", e)),
+        };
+        for frame in block.chunks_exact(channels) {
+            mono.push(frame.iter().sum::<f32>() / channels as f32);
+            if mono.len() < FFT_SIZE {
+                continue;
+            }
+            consumed += FFT_SIZE;
+            let rms = (mono.iter().map(|s| s * s).sum::<f32>() / FFT_SIZE as f32).sqrt();
+            if rms >= SILENCE_RMS {
+                for ((b, s), w) in buffer.iter_mut().zip(&mono).zip(&window) {
+                    *b = Complex::new(s * w, 0.0);
+                }
+                fft.process(&mut buffer);
+                for (p, b) in power.iter_mut().zip(&buffer) {
+                    *p += b.norm_sqr() as f64;
+                }
+                windows += 1;
+            }
+            mono.clear();
+        }
+    }
+    if windows == 0 {
+        return Err("no audible signal".to_string());
+    }
+
+    let db: Vec<f32> = power
+        .iter()
+        .map(|p| (10.0 * (p / windows as f64 + 1e-20).log10()) as f32)
+        .collect();
+    Ok(find_cutoff(&db, sample_rate))
+}
+
+/// look for the highest bin that stands out from the noise floor and decide whether the
+/// drop after it looks like an encoder lowpass.
+fn find_cutoff(db: &[f32], sample_rate: u32) -> SpectrumAnalysis {
+    let bin_hz = sample_rate as f32 / FFT_SIZE as f32;
+    let nyquist = sample_rate as f32 / 2.0;
+    let smooth: Vec<f32> = (0..db.len())
+        .map(|i| {
+            let lo = i.saturating_sub(4);
+            let hi = (i + 5).min(db.len());
+            db[lo..hi].iter().sum::<f32>() / (hi - lo) as f32
+        })
+        .collect();
+
+    // the quietest bins are noise, whether it's above the lowpass of a lossy encoder or
+    // above the anti-alias filter of a lossless master. a spectrum flat all the way up has
+    // nothing standing out from them and reaches nyquist.
+    let mut sorted = smooth.clone();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let floor = sorted[(sorted.len() as f32 * FLOOR_SHARE) as usize];
+
+    let cutoff_bin = smooth
+        .iter()
+        .rposition(|&d| d > floor + CONTENT_ABOVE_FLOOR_DB)
+        .unwrap_or(smooth.len() - 1);
+    let cutoff_hz = cutoff_bin as f32 * bin_hz;
+
+    let band = |from_hz: f32, to_hz: f32| {
+        let from = ((from_hz / bin_hz) as usize).min(smooth.len() - 1);
+        let to = ((to_hz / bin_hz) as usize).clamp(from + 1, smooth.len());
+        smooth[from..to].iter().sum::<f32>() / (to - from) as f32
+    };
+    let below = band(cutoff_hz - 1000.0, cutoff_hz - 200.0);
+    let above = band(cutoff_hz + 200.0, cutoff_hz + 1000.0);
+
+    let verdict = if nyquist - cutoff_hz < 1000.0 {
+        Verdict::Clean
+    } else if below - above >= SHARP_DROP_DB && cutoff_hz < MAX_LOSSY_CUTOFF_HZ {
+        Verdict::Lossy
+    } else {
+        Verdict::Inconclusive
+    };
+    SpectrumAnalysis { cutoff_hz, verdict }
+}
+
+/// analyze every file in `paths`, yielding each result as soon as the file is done so the
+/// ui can fill in as it goes
+pub fn analyze_all(
+    paths: Vec<PathBuf>,
+) -> impl Stream<Item = (PathBuf, Result<SpectrumAnalysis, String>)> {
+    let files = paths.into_iter().map(|path| (vec![path], ())).collect();
+    analysis::run_all(files, |paths, ()| paths.iter().map(analyze).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 44100;
+
+    /// a spectrum in dB with the level `level(hz)` in every bin
+    fn spectrum(level: impl Fn(f32) -> f32) -> Vec<f32> {
+        let bin_hz = SAMPLE_RATE as f32 / FFT_SIZE as f32;
+        (0..FFT_SIZE / 2)
+            .map(|i| level(i as f32 * bin_hz))
+            .collect()
+    }
+
+    /// content up to `cutoff_hz`, nothing but quiet noise above it
+    fn brick_wall(cutoff_hz: f32) -> Vec<f32> {
+        spectrum(|hz| if hz < cutoff_hz { -40.0 } else { -120.0 })
+    }
+
+    #[test]
+    fn flat_to_nyquist_is_clean() {
+        let analysis = find_cutoff(&spectrum(|_| -40.0), SAMPLE_RATE);
+        assert_eq!(analysis.verdict, Verdict::Clean);
+    }
+
+    #[test]
+    fn anti_alias_filter_is_clean() {
+        let analysis = find_cutoff(&brick_wall(21_500.0), SAMPLE_RATE);
+        assert_eq!(analysis.verdict, Verdict::Clean);
+    }
+
+    #[test]
+    fn encoder_lowpass_is_lossy() {
+        for cutoff in [16_000.0, 19_000.0] {
+            let analysis = find_cutoff(&brick_wall(cutoff), SAMPLE_RATE);
+            assert_eq!(analysis.verdict, Verdict::Lossy);
+            assert!(
+                (analysis.cutoff_hz - cutoff).abs() < 100.0,
+                "{:?}",
+                analysis
+            );
+        }
+    }
+
+    #[test]
+    fn gentle_roll_off_is_inconclusive() {
+        let nyquist = SAMPLE_RATE as f32 / 2.0;
+        let analysis = find_cutoff(&spectrum(|hz| -100.0 * hz / nyquist), SAMPLE_RATE);
+        assert_eq!(analysis.verdict, Verdict::Inconclusive);
+    }
+}
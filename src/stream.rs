@@ -0,0 +1,435 @@
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    mem,
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use rodio::{source::SeekError, source::UniformSourceIterator, Source};
+
+use crate::flac::{self, BLOCK_SIZE};
+
+/// format the stream is encoded in, whatever the tracks are in
+const SAMPLE_RATE: u32 = 44100;
+/// samples a [`Tap`] collects before handing them over
+const TAP_CHUNK: usize = 4096;
+/// chunks waiting to be encoded before the tap starts dropping them
+const TAP_QUEUE: usize = 64;
+/// nothing coming from the tap for this long means playback is paused or stopped, so the
+/// stream goes on with silence of the same length
+const SILENCE_AFTER: Duration = Duration::from_millis(200);
+/// frames waiting to be sent to a listener, about 3 seconds. a listener further behind
+/// misses some
+const LISTENER_QUEUE: usize = 32;
+/// a listener that doesn't take anything for this long is dropped
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+/// how long a listener has to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+/// connections served at once, counting the ones still sending their request. more are
+/// turned away
+const MAX_CONNECTIONS: usize = 32;
+/// bytes of audio between two pieces of metadata, for listeners that ask for it
+const ICY_METAINT: usize = 16000;
+const ACCEPT_POLL: Duration = Duration::from_millis(100);
+
+/// a piece of what the tap saw, all in one format
+struct Chunk {
+    sample_rate: u32,
+    channels: u16,
+    samples: Vec<f32>,
+}
+
+/// where the [`Tap`]s send what they see, nowhere while nobody is streaming
+#[derive(Default)]
+pub struct TapSlot(Mutex<Option<SyncSender<Chunk>>>);
+
+/// passes a source through, sending a copy of it to the stream if there is one. it sits
+/// in front of the sink, so the stream gets what's playing before the volume is applied.
+pub struct Tap<S> {
+    source: S,
+    slot: Arc<TapSlot>,
+    buffer: Vec<f32>,
+    /// sample rate and channels of what's in `buffer`
+    format: (u32, u16),
+}
+
+impl<S: Source<Item = f32>> Tap<S> {
+    pub fn new(source: S, slot: Arc<TapSlot>) -> Tap<S> {
+        Tap {
+            format: (source.sample_rate(), source.channels()),
+            source,
+            slot,
+            buffer: Vec::with_capacity(TAP_CHUNK),
+        }
+    }
+
+    fn flush(&mut self) {
+        // never wait on the engine or the encoder here, this runs on the audio thread
+        let slot = self.slot.0.try_lock();
+        match slot.as_deref() {
+            Ok(Some(tx)) if !self.buffer.is_empty() => {
+                let chunk = Chunk {
+                    sample_rate: self.format.0,
+                    channels: self.format.1,
+                    samples: mem::replace(&mut self.buffer, Vec::with_capacity(TAP_CHUNK)),
+                };
+                let _ = tx.try_send(chunk);
+            }
+            _ => self.buffer.clear(),
+        }
+    }
+}
+
+impl<S: Source<Item = f32>> Iterator for Tap<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let format = (self.source.sample_rate(), self.source.channels());
+        if format != self.format {
+            self.flush();
+            self.format = format;
+        }
+        let sample = self.source.next()?;
+        self.buffer.push(sample);
+        if self.buffer.len() >= TAP_CHUNK && self.buffer.len().is_multiple_of(format.1 as usize) {
+            self.flush();
+        }
+        Some(sample)
+    }
+}
+
+impl<S: Source<Item = f32>> Source for Tap<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.buffer.clear();
+        self.source.try_seek(pos)
+    }
+}
+
+/// the chunks coming from the tap as a source again, with silence filled in while there
+/// are none
+struct Received {
+    rx: Receiver<Chunk>,
+    chunk: Chunk,
+    pos: usize,
+}
+
+impl Received {
+    fn new(rx: Receiver<Chunk>) -> Received {
+        let mut received = Received {
+            rx,
+            chunk: Chunk {
+                sample_rate: SAMPLE_RATE,
+                channels: 2,
+                samples: Vec::new(),
+            },
+            pos: 0,
+        };
+        received.refill();
+        received
+    }
+
+    fn refill(&mut self) {
+        self.pos = 0;
+        self.chunk = match self.rx.recv_timeout(SILENCE_AFTER) {
+            Ok(chunk) => chunk,
+            Err(RecvTimeoutError::Timeout) => {
+                let (rate, channels) = (self.chunk.sample_rate, self.chunk.channels);
+                let frames = (SILENCE_AFTER.as_secs_f64() * rate as f64) as usize;
+                Chunk {
+                    sample_rate: rate,
+                    channels,
+                    samples: vec![0.0; frames * channels as usize],
+                }
+            }
+            // the stream was stopped
+            Err(RecvTimeoutError::Disconnected) => Chunk {
+                samples: Vec::new(),
+                ..self.chunk
+            },
+        };
+    }
+}
+
+impl Iterator for Received {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = *self.chunk.samples.get(self.pos)?;
+        self.pos += 1;
+        // refill right away so current_frame_len always knows about format changes
+        if self.pos == self.chunk.samples.len() {
+            self.refill();
+        }
+        Some(sample)
+    }
+}
+
+impl Source for Received {
+    fn current_frame_len(&self) -> Option<usize> {
+        Some(self.chunk.samples.len() - self.pos)
+    }
+
+    fn channels(&self) -> u16 {
+        self.chunk.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.chunk.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// what the threads of a stream share
+#[derive(Default)]
+struct Shared {
+    /// shown by the listeners' players, usually artist and title
+    title: Mutex<String>,
+    listeners: Mutex<Vec<SyncSender<Arc<[u8]>>>>,
+    /// connections with a thread serving them
+    connections: AtomicUsize,
+    stopped: AtomicBool,
+}
+
+/// serves what's playing over http as a FLAC stream, with ICY metadata for the players that
+/// ask for it. stops when dropped.
+pub struct Streamer {
+    pub port: u16,
+    slot: Arc<TapSlot>,
+    shared: Arc<Shared>,
+}
+
+impl Streamer {
+    /// start listening on `port` on every interface and feed the stream from the taps
+    /// sending to `slot`
+    pub fn start(port: u16, slot: Arc<TapSlot>) -> Result<Streamer, String> {
+        let listener = TcpListener::bind(("0.0.0.0", port))
+            .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+            .map_err(|e| format!("could not stream on port {}: {}", port, e))?;
+        let shared = Arc::new(Shared::default());
+        let (tx, rx) = sync_channel(TAP_QUEUE);
+        *slot.0.lock().expect("tap lock poisoned") = Some(tx);
+
+        let encoder_shared = shared.clone();
+        thread::Builder::new()
+            .name("stream encoder".to_string())
+            .spawn(move || encode(rx, &encoder_shared))
+            .expect("failed to spawn stream encoder");
+        let accept_shared = shared.clone();
+        thread::Builder::new()
+            .name("stream listener".to_string())
+            .spawn(move || accept(listener, accept_shared))
+            .expect("failed to spawn stream listener");
+        Ok(Streamer { port, slot, shared })
+    }
+
+    pub fn set_title(&self, title: String) {
+        *self
+            .shared
+            .title
+            .lock()
+            .expect("stream title lock poisoned") = title;
+    }
+}
+
+impl Drop for Streamer {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::Relaxed);
+        // the encoder runs out once its sender is gone, and takes the listeners with it
+        *self.slot.0.lock().expect("tap lock poisoned") = None;
+    }
+}
+
+/// encode what comes from the tap and hand the frames to every listener
+fn encode(rx: Receiver<Chunk>, shared: &Shared) {
+    let mut samples: UniformSourceIterator<_, f32> =
+        UniformSourceIterator::new(Received::new(rx), 2, SAMPLE_RATE);
+    let mut encoder = flac::Encoder::new(SAMPLE_RATE);
+    let mut block = Vec::with_capacity(BLOCK_SIZE * 2);
+    loop {
+        block.clear();
+        block.extend(samples.by_ref().take(BLOCK_SIZE * 2));
+        if block.len() < BLOCK_SIZE * 2 {
+            return;
+        }
+        let frame: Arc<[u8]> = encoder.frame(&block).into();
+        let mut listeners = shared.listeners.lock().expect("listeners lock poisoned");
+        listeners.retain(|tx| match tx.try_send(frame.clone()) {
+            Ok(()) | Err(TrySendError::Full(_)) => true,
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+}
+
+fn accept(listener: TcpListener, shared: Arc<Shared>) {
+    while !shared.stopped.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((mut stream, address)) => {
+                // only this thread adds connections, so the count can't go past the limit
+                if shared.connections.load(Ordering::Relaxed) >= MAX_CONNECTIONS {
+                    println!("error: turned away stream listener {}, too many", address);
+                    let _ = stream.write_all(b"HTTP/1.0 503 Service Unavailable\r\n\r\n");
+                    continue;
+                }
+                shared.connections.fetch_add(1, Ordering::Relaxed);
+                let thread_shared = shared.clone();
+                let spawned = thread::Builder::new()
+                    .name(format!("stream to {}", address))
+                    .spawn(move || {
+                        if let Err(e) = serve(stream, &thread_shared) {
+                            println!("stream to {} ended: {}", address, e);
+                        }
+                        thread_shared.connections.fetch_sub(1, Ordering::Relaxed);
+                    });
+                if let Err(e) = spawned {
+                    shared.connections.fetch_sub(1, Ordering::Relaxed);
+                    println!("error: could not serve stream to {}: {}", address, e);
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL),
+            Err(e) => {
+                println!("error: could not accept stream listener: {}", e);
+                thread::sleep(ACCEPT_POLL);
+            }
+        }
+    }
+}
+
+/// answer one http request with the stream, until either end stops
+fn serve(mut stream: TcpStream, shared: &Shared) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let request = read_request(&mut stream)?;
+    if !request.starts_with("GET ") {
+        return stream.write_all(b"HTTP/1.0 405 Method Not Allowed\r\nAllow: GET\r\n\r\n");
+    }
+    let icy = request.lines().any(|line| {
+        line.split_once(':').is_some_and(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("icy-metadata") && value.trim() == "1"
+        })
+    });
+    let mut headers = "HTTP/1.0 200 OK\r\n\
+        Content-Type: audio/flac\r\n\
+        Cache-Control: no-cache, no-store\r\n\
+        icy-name: thump\r\n"
+        .to_string();
+    if icy {
+        headers += &format!("icy-metaint: {}\r\n", ICY_METAINT);
+    }
+    headers += "\r\n";
+    stream.write_all(headers.as_bytes())?;
+
+    let (tx, rx) = sync_channel(LISTENER_QUEUE);
+    shared
+        .listeners
+        .lock()
+        .expect("listeners lock poisoned")
+        .push(tx);
+    let mut out = IcyWriter {
+        stream,
+        shared,
+        metaint: icy.then_some(ICY_METAINT),
+        until_metadata: ICY_METAINT,
+        sent_title: String::new(),
+    };
+    // a listener can come in at any frame, they all stand on their own
+    out.write(&flac::stream_header(SAMPLE_RATE))?;
+    for frame in rx {
+        out.write(&frame)?;
+    }
+    Ok(())
+}
+
+/// the request line and headers, up to the empty line
+fn read_request(stream: &mut TcpStream) -> io::Result<String> {
+    let mut request = Vec::new();
+    let mut byte = [0; 1];
+    while !request.ends_with(b"\r\n\r\n") && !request.ends_with(b"\n\n") {
+        if request.len() > 16 * 1024 {
+            return Err(io::Error::new(ErrorKind::InvalidData, "request too long"));
+        }
+        // a byte at a time so nothing after the headers is read
+        if stream.read(&mut byte)? == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        request.push(byte[0]);
+    }
+    Ok(String::from_utf8_lossy(&request).into_owned())
+}
+
+/// writes the audio, with the title in between every `metaint` bytes if asked for
+struct IcyWriter<'a> {
+    stream: TcpStream,
+    shared: &'a Shared,
+    metaint: Option<usize>,
+    until_metadata: usize,
+    /// the title the listener has, it's only sent again when it changes
+    sent_title: String,
+}
+
+impl IcyWriter<'_> {
+    fn write(&mut self, mut data: &[u8]) -> io::Result<()> {
+        let Some(metaint) = self.metaint else {
+            return self.stream.write_all(data);
+        };
+        while !data.is_empty() {
+            let len = data.len().min(self.until_metadata);
+            self.stream.write_all(&data[..len])?;
+            data = &data[len..];
+            self.until_metadata -= len;
+            if self.until_metadata == 0 {
+                let metadata = self.metadata();
+                self.stream.write_all(&metadata)?;
+                self.until_metadata = metaint;
+            }
+        }
+        Ok(())
+    }
+
+    /// a length byte counting 16 byte blocks, and the blocks. no blocks if nothing changed
+    fn metadata(&mut self) -> Vec<u8> {
+        let title = self
+            .shared
+            .title
+            .lock()
+            .expect("stream title lock poisoned")
+            .clone();
+        if title == self.sent_title {
+            return vec![0];
+        }
+        // there's no escaping in there, so quotes would end the title early
+        let mut text = format!("StreamTitle='{}';", title.replace('\'', "\u{2019}")).into_bytes();
+        text.truncate(255 * 16);
+        let blocks = text.len().div_ceil(16);
+        let mut metadata = vec![blocks as u8];
+        metadata.extend(&text);
+        metadata.resize(1 + blocks * 16, 0);
+        self.sent_title = title;
+        metadata
+    }
+}
@@ -0,0 +1,186 @@
+use std::f32::consts::PI;
+
+/// slowest and fastest playback speed
+pub const MIN_SPEED: f32 = 0.5;
+pub const MAX_SPEED: f32 = 3.0;
+/// how far the pitch can be shifted either way, in semitones
+pub const MAX_PITCH: f32 = 12.0;
+/// length of the pieces the input is cut into, long enough to hold a few periods of a voice
+const WINDOW_SECONDS: f32 = 0.03;
+/// how far a piece may be moved to line up with the one before it
+const SEARCH_SECONDS: f32 = 0.01;
+
+/// changes the tempo without changing the pitch and the other way round, with WSOLA.
+/// the input is cut into overlapping pieces that are laid down closer together or further
+/// apart, each one moved a little so its waveform lines up with the one before it. pitch
+/// shifting stretches by the pitch ratio as well and resamples the result back.
+pub struct Stretcher {
+    speed: f32,
+    /// ratio the pitch is shifted by, 2 is an octave up
+    pitch: f32,
+    sample_rate: u32,
+    channels: usize,
+    /// input not used up yet, interleaved
+    input: Vec<f32>,
+    /// where in `input` the next piece should come from, in frames
+    position: f64,
+    /// where in `input` the previous piece would have carried on, `None` before the first
+    natural: Option<usize>,
+    /// second half of the previous piece, windowed, waiting to be added to the next one
+    tail: Vec<f32>,
+    /// stretched output waiting to be resampled for the pitch
+    stretched: Vec<f32>,
+    /// position of the resampler in `stretched`, in frames
+    resample_pos: f64,
+}
+
+impl Default for Stretcher {
+    fn default() -> Self {
+        Stretcher {
+            speed: 1.0,
+            pitch: 1.0,
+            sample_rate: 0,
+            channels: 0,
+            input: Vec::new(),
+            position: 0.0,
+            natural: None,
+            tail: Vec::new(),
+            stretched: Vec::new(),
+            resample_pos: 0.0,
+        }
+    }
+}
+
+impl Stretcher {
+    /// `speed` scales the tempo, `semitones` moves the pitch
+    pub fn set(&mut self, speed: f32, semitones: f32) {
+        self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+        self.pitch = 2f32.powf(semitones.clamp(-MAX_PITCH, MAX_PITCH) / 12.0);
+        if !self.is_active() {
+            self.reset();
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.speed != 1.0 || self.pitch != 1.0
+    }
+
+    /// forget any buffered audio, after a seek or a change of format
+    pub fn reset(&mut self) {
+        self.input.clear();
+        self.position = 0.0;
+        self.natural = None;
+        self.tail.clear();
+        self.stretched.clear();
+        self.resample_pos = 0.0;
+    }
+
+    /// stretch an interleaved block in place. the block can come out empty while the
+    /// stretcher is still collecting enough input to work with.
+    pub fn process(&mut self, block: &mut Vec<f32>, sample_rate: u32, channels: usize) {
+        if !self.is_active() {
+            return;
+        }
+        if (sample_rate, channels) != (self.sample_rate, self.channels) {
+            self.sample_rate = sample_rate;
+            self.channels = channels;
+            self.reset();
+        }
+        self.input.append(block);
+        self.stretch();
+        self.resample(block);
+    }
+
+    /// lay down as many pieces as the input allows, into `stretched`
+    fn stretch(&mut self) {
+        let channels = self.channels;
+        let window = (WINDOW_SECONDS * self.sample_rate as f32) as usize & !1;
+        let hop = window / 2;
+        let search = (SEARCH_SECONDS * self.sample_rate as f32) as usize;
+        // the pitch shift resamples by `pitch` afterwards, which also speeds things up
+        let step = hop as f64 * (self.speed / self.pitch) as f64;
+
+        loop {
+            let nominal = self.position.round() as usize;
+            if (nominal + search + window) * channels > self.input.len() {
+                break;
+            }
+            let start = match self.natural {
+                Some(natural) => self.best_match(natural, nominal, hop, search),
+                None => nominal,
+            };
+            if self.tail.is_empty() {
+                self.tail = vec![0.0; hop * channels];
+            }
+            for i in 0..window {
+                // periodic hann, two halves overlapping by half add up to exactly one
+                let weight = 0.5 - 0.5 * (2.0 * PI * i as f32 / window as f32).cos();
+                for c in 0..channels {
+                    let sample = self.input[(start + i) * channels + c] * weight;
+                    if i < hop {
+                        self.stretched.push(self.tail[i * channels + c] + sample);
+                    } else {
+                        self.tail[(i - hop) * channels + c] = sample;
+                    }
+                }
+            }
+            self.position += step;
+
+            // drop the input nothing will look at again
+            let natural = start + hop;
+            let used = natural.min((self.position as usize).saturating_sub(search));
+            self.input.drain(..used * channels);
+            self.position -= used as f64;
+            self.natural = Some(natural - used);
+        }
+    }
+
+    /// the start within `search` frames of `nominal` whose first `len` frames look most
+    /// like the ones at `target`, the natural continuation of the previous piece
+    fn best_match(&self, target: usize, nominal: usize, len: usize, search: usize) -> usize {
+        let channels = self.channels;
+        let mono = |frame: usize| -> f32 {
+            self.input[frame * channels..(frame + 1) * channels]
+                .iter()
+                .sum()
+        };
+        let mut best = (f32::MIN, nominal);
+        for start in nominal.saturating_sub(search)..=nominal + search {
+            // every other frame is close enough and halves the work
+            let (mut correlation, mut energy) = (0.0, 0.0);
+            for i in (0..len).step_by(2) {
+                let candidate = mono(start + i);
+                correlation += candidate * mono(target + i);
+                energy += candidate * candidate;
+            }
+            let score = correlation / energy.sqrt().max(1e-9);
+            if score > best.0 {
+                best = (score, start);
+            }
+        }
+        best.1
+    }
+
+    /// resample `stretched` by the pitch ratio into `out`
+    fn resample(&mut self, out: &mut Vec<f32>) {
+        let channels = self.channels;
+        if self.pitch == 1.0 {
+            out.append(&mut self.stretched);
+            return;
+        }
+        let frames = self.stretched.len() / channels;
+        while self.resample_pos + 1.0 < frames as f64 {
+            let index = self.resample_pos as usize;
+            let t = (self.resample_pos - index as f64) as f32;
+            for c in 0..channels {
+                let a = self.stretched[index * channels + c];
+                let b = self.stretched[(index + 1) * channels + c];
+                out.push(a + (b - a) * t);
+            }
+            self.resample_pos += self.pitch as f64;
+        }
+        let used = (self.resample_pos as usize).min(frames);
+        self.stretched.drain(..used * channels);
+        self.resample_pos -= used as f64;
+    }
+}
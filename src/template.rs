@@ -0,0 +1,97 @@
+use std::path::PathBuf;
+
+use crate::Song;
+
+/// characters that aren't allowed (or are a bad idea) in file names on at least one platform
+const UNSAFE_CHARS: [char; 9] = ['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+/// render a path template like `{album_artist}/{year} - {album}/{disc}-{track:02} {title}.{ext}`
+/// for `song`. `/` in the template separates directories, values are sanitized so they never
+/// do. a `:NN` suffix zero pads numbers to `NN` digits. unknown fields are left as is.
+///
+/// # Fields
+/// `title`, `artist`, `album_artist`, `album`, `year`, `track`, `disc`, `ext`
+pub fn render(template: &str, song: &Song) -> PathBuf {
+    template
+        .split('/')
+        .map(|component| sanitize(&render_component(component, song)))
+        .collect()
+}
+
+fn render_component(component: &str, song: &Song) -> String {
+    let mut out = String::new();
+    let mut rest = component;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let Some(len) = rest[start..].find('}') else {
+            rest = &rest[start..];
+            break;
+        };
+        let field = &rest[start + 1..start + len];
+        match field_value(field, song) {
+            Some(value) => out.push_str(&value),
+            None => out.push_str(&rest[start..=start + len]),
+        }
+        rest = &rest[start + len + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn field_value(field: &str, song: &Song) -> Option<String> {
+    let (name, width) = match field.split_once(':') {
+        Some((name, width)) => (name, width.parse::<usize>().ok()?),
+        None => (field, 0),
+    };
+    let number = |n: Option<i32>, default: i32| format!("{:0width$}", n.unwrap_or(default));
+    let text = |s: &Option<String>, default: &str| {
+        s.clone()
+            .filter(|s| !s.trim().is_empty())
+            .unwrap_or_else(|| default.to_string())
+    };
+    let value = match name {
+        "title" => text(&song.name, "Unknown Title"),
+        "artist" => text(&song.track_artist, "Unknown Artist"),
+        "album_artist" => text(
+            &song.album_artist.clone().or(song.track_artist.clone()),
+            "Unknown Artist",
+        ),
+        "album" => text(&song.album_name, "Unknown Album"),
+        "year" => song
+            .recording_date
+            .as_ref()
+            .and_then(|d| d.get(..4))
+            .unwrap_or("0000")
+            .to_string(),
+        "track" => number(song.track_number, 0),
+        "disc" => number(song.disc_number, 1),
+        "ext" => song
+            .path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default(),
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// make `name` safe to use as a single path component
+pub fn sanitize(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if UNSAFE_CHARS.contains(&c) || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    // windows strips trailing dots and spaces, and a leading dot hides the file elsewhere
+    let name = name.trim().trim_end_matches('.').trim_start_matches('.');
+    if name.is_empty() {
+        "_".to_string()
+    } else {
+        name.to_string()
+    }
+}